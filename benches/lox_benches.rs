@@ -2,44 +2,73 @@
 extern crate criterion;
 extern crate rustilox;
 
+use std::io;
+
 use criterion::Criterion;
+use rustilox::{Scanner, Value};
+use rustilox::bench::{Chunk, OpCode, ValueArray, VM};
 
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("scan strings", |b| {
         let src: String = "\"I am a string\"".to_string();
         b.iter(|| {
-            rustilox::interpret(&src)
+            Scanner::init(&src).count()
         })
     });
 
     c.bench_function("scan numbers", |b| {
         let src: String = "3.14159263".to_string();
         b.iter(|| {
-            rustilox::interpret(&src)
+            Scanner::init(&src).count()
         })
     });
 
     c.bench_function("scan keywords", |b| {
         let src: String = "while true false for and print".to_string();
         b.iter(|| {
-            rustilox::interpret(&src)
+            Scanner::init(&src).count()
         })
     });
 
     c.bench_function("scan identifiers", |b| {
         let src: String = "Leonardo Donatello Michelangelo Raphael".to_string();
         b.iter(|| {
-            rustilox::interpret(&src)
+            Scanner::init(&src).count()
         })
     });
 
     c.bench_function("scan comments", |b| {
         let src: String = "// I am a comment!".to_string();
         b.iter(|| {
-            rustilox::interpret(&src)
+            Scanner::init(&src).count()
+        })
+    });
+}
+
+fn vm_benchmark(c: &mut Criterion) {
+    // a long run of arithmetic assembled by hand, so that the time is spent
+    // dispatching instructions rather than compiling or printing the result.
+    // with output going to a sink, moving the VM to a preallocated stack with
+    // in-place binary ops took this from 34.3us [33.9 .. 34.8] to 20.4us
+    // [20.0 .. 20.7] per run; the features added since put it at ~50us
+    let ops = [OpCode::Add, OpCode::Subtract, OpCode::Multiply, OpCode::Divide];
+    let mut code: Vec<u8> = vec![OpCode::Constant as u8, 0];
+    for i in 0..2500 {
+        code.extend_from_slice(&[OpCode::Constant as u8, 1 + (i % 4) as u8, ops[i % 4] as u8]);
+    }
+    code.push(OpCode::Return as u8);
+    let lines = vec![1; code.len()];
+    let constants = ValueArray::build(vec![Value::Real(1.5), Value::Real(2.25), Value::Real(1.25), Value::Real(1.0), Value::Real(1.0)]);
+    let chunk = Chunk::build(constants, code, lines);
+
+    c.bench_function("vm arithmetic", move |b| {
+        b.iter(|| {
+            let mut vm = VM::init(&chunk);
+            vm.set_output(Box::new(io::sink()));
+            vm.evaluate()
         })
     });
 }
 
-criterion_group!(benches, criterion_benchmark);
+criterion_group!(benches, criterion_benchmark, vm_benchmark);
 criterion_main!(benches);
\ No newline at end of file
@@ -0,0 +1,241 @@
+//! Derive macros for exposing Rust structs to Lox scripts as classes
+//!
+//! `#[derive(LoxClass)]` lets scripts read and assign a struct's fields, and
+//! `#[lox_methods]` on an `impl` block exposes its methods, with an `init`
+//! function becoming the constructor. Register the class with
+//! `Lox::define_class`.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as Tokens;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, FnArg, Ident, ImplItem, ItemImpl, Pat, ReturnType, Type};
+
+/// Expose a struct's named fields as properties. Fields marked `#[lox(skip)]`
+/// are hidden, and those marked `#[lox(readonly)]` can't be assigned.
+/// Exposed fields must be `Clone` and convert with `IntoLox` and `FromLox`.
+#[proc_macro_derive(LoxClass, attributes(lox))]
+pub fn derive_lox_class(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match lox_class(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+/// Expose the methods of an `impl` block. Methods taking `&self` or
+/// `&mut self` can be invoked, and an associated `init` returning `Self` is
+/// called when a script calls the class. Arguments convert with `FromLox`,
+/// or borrow another instance when they're a `&Class`, and results convert
+/// with `IntoLox` or as a `Result<_, LoxError>`.
+#[proc_macro_attribute]
+pub fn lox_methods(_args: TokenStream, input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as ItemImpl);
+    match lox_methods_impl(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+/// Which of `skip` and `readonly` a field is marked with
+fn field_options(attrs: &[syn::Attribute]) -> syn::Result<(bool, bool)> {
+    let mut skip = false;
+    let mut readonly = false;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("lox")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            }
+            else if meta.path.is_ident("readonly") {
+                readonly = true;
+                Ok(())
+            }
+            else {
+                Err(meta.error("expected `skip` or `readonly`"))
+            }
+        })?;
+    }
+    Ok((skip, readonly))
+}
+
+fn lox_class(input: &DeriveInput) -> syn::Result<Tokens> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            Fields::Unit => return Ok(class_impl(input, vec![], vec![])),
+            Fields::Unnamed(_) => return Err(Error::new_spanned(input, "LoxClass needs named fields"))
+        },
+        _ => return Err(Error::new_spanned(input, "LoxClass can only be derived for structs"))
+    };
+
+    let mut getters = Vec::new();
+    let mut setters = Vec::new();
+    for field in fields {
+        let (skip, readonly) = field_options(&field.attrs)?;
+        if skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().unwrap();
+        let name = ident.to_string();
+        getters.push(quote! {
+            #name => Some(::rustilox::IntoLox::into_lox(::std::clone::Clone::clone(&self.#ident)))
+        });
+        if !readonly {
+            setters.push(quote! {
+                #name => Some(::rustilox::FromLox::from_lox(value).map(|v| { self.#ident = v; }).map_err(::std::convert::From::from))
+            });
+        }
+    }
+    Ok(class_impl(input, getters, setters))
+}
+
+fn class_impl(input: &DeriveInput, getters: Vec<Tokens>, setters: Vec<Tokens>) -> Tokens {
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rustilox::LoxClass for #ident #type_generics #where_clause {
+            const NAME: &'static str = #name;
+
+            fn get_field(&self, name: &str) -> ::std::option::Option<::rustilox::Value> {
+                match name {
+                    #(#getters,)*
+                    _ => None
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn set_field(&mut self, name: &str, value: ::rustilox::Value) -> ::std::option::Option<::std::result::Result<(), ::rustilox::LoxError>> {
+                match name {
+                    #(#setters,)*
+                    _ => None
+                }
+            }
+        }
+    }
+}
+
+/// The types of a function's arguments, other than its receiver
+fn argument_types(sig: &syn::Signature) -> syn::Result<Vec<&Type>> {
+    sig.inputs.iter()
+        .filter_map(|input| match *input {
+            FnArg::Receiver(_) => None,
+            FnArg::Typed(ref arg) => Some(arg)
+        })
+        .map(|arg| match *arg.pat {
+            Pat::Ident(_) | Pat::Wild(_) => Ok(&*arg.ty),
+            _ => Err(Error::new_spanned(&arg.pat, "lox methods can't destructure their arguments"))
+        })
+        .collect()
+}
+
+/// Code converting the method arguments of `types` into `arg0`, `arg1`...,
+/// and the expressions passing them to the method
+fn method_arguments(types: &[&Type]) -> (Vec<Tokens>, Vec<Tokens>) {
+    let mut bindings = Vec::new();
+    let mut passes = Vec::new();
+    for (i, ty) in types.iter().enumerate() {
+        let arg = Ident::new(&format!("arg{}", i), proc_macro2::Span::call_site());
+        match **ty {
+            Type::Reference(ref reference) if reference.mutability.is_none() => {
+                let class = &reference.elem;
+                bindings.push(quote! {
+                    let #arg = <::std::rc::Rc<::rustilox::Instance<#class>> as ::rustilox::FromLox>::from_lox(args.next().unwrap())?;
+                    let #arg = #arg.try_borrow()?;
+                });
+                passes.push(quote! { &*#arg });
+            },
+            _ => {
+                bindings.push(quote! {
+                    let #arg = <#ty as ::rustilox::FromLox>::from_lox(args.next().unwrap())?;
+                });
+                passes.push(quote! { #arg });
+            }
+        }
+    }
+    (bindings, passes)
+}
+
+fn lox_methods_impl(input: &ItemImpl) -> syn::Result<Tokens> {
+    let self_type = &input.self_ty;
+    let mut constructor = None;
+    let mut methods = Vec::new();
+
+    for item in &input.items {
+        let method = match *item {
+            ImplItem::Fn(ref method) => method,
+            _ => continue
+        };
+        let sig = &method.sig;
+        let ident = &sig.ident;
+        let types = argument_types(sig)?;
+
+        if let Some(receiver) = sig.receiver() {
+            let name = ident.to_string();
+            let count = types.len();
+            let (bindings, passes) = method_arguments(&types);
+            // arguments are borrowed first, so a `&mut self` method given its
+            // own instance fails to borrow it rather than panicking
+            let this = if receiver.mutability.is_some() {
+                quote! { let mut this = instance.try_borrow_mut()?; }
+            }
+            else {
+                quote! { let this = instance.try_borrow()?; }
+            };
+            methods.push(quote! {
+                #name => Some((|| {
+                    ::rustilox::derive::check_arity(#count, args.len())?;
+                    let mut args = args.into_iter();
+                    #(#bindings)*
+                    #this
+                    ::rustilox::IntoLoxResult::into_lox_result(this.#ident(#(#passes),*))
+                })())
+            });
+        }
+        else if ident == "init" {
+            if let ReturnType::Default = sig.output {
+                return Err(Error::new_spanned(sig, "init must return the class"));
+            }
+            let parameters: Vec<Tokens> = (0..types.len())
+                .map(|i| Ident::new(&format!("arg{}", i), proc_macro2::Span::call_site()))
+                .zip(&types)
+                .map(|(arg, ty)| quote! { #arg: #ty })
+                .collect();
+            let names = (0..types.len()).map(|i| Ident::new(&format!("arg{}", i), proc_macro2::Span::call_site()));
+            constructor = Some(quote! {
+                ::rustilox::Native::wrap(<Self as ::rustilox::LoxClass>::NAME, |#(#parameters),*| {
+                    ::std::rc::Rc::new(::rustilox::Instance::init(<#self_type>::init(#(#names),*)))
+                })
+            });
+        }
+    }
+
+    let constructor = constructor.unwrap_or_else(|| quote! {
+        ::rustilox::Native::init(<Self as ::rustilox::LoxClass>::NAME, 0, |_| {
+            Err(::rustilox::LoxError::RuntimeError(format!("{} has no init", <Self as ::rustilox::LoxClass>::NAME), 0))
+        })
+    });
+    let (impl_generics, _, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        #input
+
+        impl #impl_generics ::rustilox::LoxMethods for #self_type #where_clause {
+            fn constructor() -> ::rustilox::Native {
+                #constructor
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn invoke_method(instance: &::rustilox::Instance<Self>, name: &str, args: ::std::vec::Vec<::rustilox::Value>) -> ::std::option::Option<::std::result::Result<::rustilox::Value, ::rustilox::LoxError>> {
+                match name {
+                    #(#methods,)*
+                    _ => None
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,133 @@
+extern crate rustilox;
+#[macro_use] extern crate rustilox_derive;
+
+use std::io;
+use std::rc::Rc;
+
+use rustilox::{HostObject, Lox, LoxError, Value};
+use rustilox_derive::lox_methods;
+
+#[derive(LoxClass)]
+struct Point {
+    x: f64,
+    y: f64,
+    #[lox(readonly)]
+    label: String,
+    #[lox(skip)]
+    moves: usize
+}
+
+#[lox_methods]
+impl Point {
+    fn init(x: f64, y: f64) -> Point {
+        Point { x, y, label: format!("({}, {})", x, y), moves: 0 }
+    }
+
+    fn length(&self) -> f64 {
+        self.x.hypot(self.y)
+    }
+
+    fn translate(&mut self, dx: f64, dy: f64) -> i64 {
+        self.x += dx;
+        self.y += dy;
+        self.moves += 1;
+        self.moves as i64
+    }
+
+    fn scale(&mut self, k: f64) -> Result<(), LoxError> {
+        if k == 0.0 {
+            return Err(LoxError::RuntimeError("can't scale by zero".to_string(), 0));
+        }
+        self.x *= k;
+        self.y *= k;
+        Ok(())
+    }
+
+    fn plus(&self, other: Rc<dyn HostObject>) -> Result<Value, LoxError> {
+        other.get("x")
+    }
+
+    fn distance(&self, other: &Point) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+
+    fn absorb(&mut self, other: &Point) {
+        self.x += other.x;
+        self.y += other.y;
+    }
+
+    /// not exposed: it has no receiver and isn't `init`
+    fn origin() -> Point {
+        Point::init(0.0, 0.0)
+    }
+}
+
+#[derive(LoxClass)]
+struct Marker;
+
+#[lox_methods]
+impl Marker {}
+
+fn lox() -> Lox {
+    let mut lox = Lox::init();
+    lox.set_output(Box::new(io::sink()));
+    lox.set_diagnostics(Box::new(io::sink()));
+    lox.define_class::<Point>();
+    lox.define_class::<Marker>();
+    lox.set_global("p", Value::Nil);
+    lox
+}
+
+fn error(lox: &mut Lox, source: &str) -> String {
+    match lox.eval(source) {
+        Err(LoxError::RuntimeError(msg, _)) => msg,
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+fn constructs_classes_and_calls_methods() {
+    let mut lox = lox();
+    assert_eq!(lox.eval("p = Point(3, 4)").unwrap().to_string(), "Point instance");
+    assert_eq!(lox.eval("p.length()").unwrap(), Value::Real(5.0));
+    assert_eq!(lox.eval("p.translate(1, 1) + p.translate(-1, -1)").unwrap(), Value::Natural(3));
+    assert_eq!(lox.eval("p.x = 6").unwrap(), Value::Natural(6));
+    assert_eq!(lox.eval("p.x + p.y").unwrap(), Value::Real(10.0));
+    assert_eq!(lox.eval("p.label").unwrap(), Value::String(Rc::from("(3, 4)")));
+    assert_eq!(lox.eval("p.scale(2)").unwrap(), Value::Nil);
+    assert_eq!(lox.eval("p.x + p.y").unwrap(), Value::Real(20.0));
+    assert_eq!(Point::origin().length(), 0.0);
+}
+
+#[test]
+fn reports_misuse_as_runtime_errors() {
+    let mut lox = lox();
+    lox.eval("p = Point(3, 4)").unwrap();
+    assert_eq!(error(&mut lox, "Point(1)"), "expected 2 arguments but got 1");
+    assert_eq!(error(&mut lox, "Point(1, true)"), "expected number, got bool");
+    assert_eq!(error(&mut lox, "p.length(1)"), "expected 0 arguments but got 1");
+    assert_eq!(error(&mut lox, "p.translate(\"a\", 1)"), "expected number, got string");
+    assert_eq!(error(&mut lox, "p.scale(0)"), "can't scale by zero");
+    assert_eq!(error(&mut lox, "p.x = nil"), "expected number, got nil");
+    assert_eq!(error(&mut lox, "p.label = \"a\""), "can't set property 'label' on Point");
+    assert_eq!(error(&mut lox, "p.moves"), "undefined property 'moves' on Point");
+    assert_eq!(error(&mut lox, "p.origin()"), "undefined method 'origin' on Point");
+    assert_eq!(error(&mut lox, "Marker()"), "Marker has no init");
+    assert_eq!(error(&mut lox, "p.distance(Marker)"), "expected Point, got native");
+}
+
+#[test]
+fn passes_instances_to_their_own_methods() {
+    let mut lox = lox();
+    lox.eval("p = Point(3, 4)").unwrap();
+    assert_eq!(lox.eval("p.plus(Point(2, 0))").unwrap(), Value::Real(2.0));
+    assert_eq!(lox.eval("p.plus(p)").unwrap(), Value::Real(3.0));
+    assert_eq!(lox.eval("p.distance(p)").unwrap(), Value::Real(0.0));
+    assert_eq!(lox.eval("p.distance(Point(0, 0))").unwrap(), Value::Real(5.0));
+    assert_eq!(lox.eval("p.absorb(Point(1, 1))").unwrap(), Value::Nil);
+    assert_eq!(lox.eval("p.x").unwrap(), Value::Real(4.0));
+
+    // a method changing its instance can't also read it
+    assert_eq!(error(&mut lox, "p.absorb(p)"), "can't change Point while it's in use");
+    assert_eq!(lox.eval("p.x").unwrap(), Value::Real(4.0));
+}
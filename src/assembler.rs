@@ -0,0 +1,207 @@
+//! A text format for writing chunks by hand, one instruction per line
+//!
+//! ```text
+//! ; comments run from a semicolon to the end of the line
+//!     OP_CONSTANT 1.5          ; numbers, "strings", true, false or nil
+//!     OP_GET_GLOBAL clock      ; names are identifiers or "strings"
+//!     OP_CALL 0                ; counts are plain numbers
+//!     OP_JUMP_IF_FALSE done    ; jumps name a label further down
+//!     OP_INVOKE read 1         ; a method name, then its argument count
+//! done:
+//!     OP_RETURN
+//! ```
+//!
+//! Each instruction keeps the line it was written on, so runtime errors
+//! point back into the assembly.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use chunk::{Chunk, MAX_CONSTANTS};
+use compiler::literal;
+use errors::LoxError;
+use opcodes::{OpCode, Operands};
+use values::Value;
+use verifier::verify;
+
+/// Assemble `source` into a chunk, which is verified before it's returned
+pub fn assemble(source: &str) -> Result<Chunk, LoxError> {
+    let mut chunk = Chunk::init();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    // (offset of the jump's operand, label it jumps to, line)
+    let mut jumps: Vec<(usize, &str, usize)> = Vec::new();
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let words = words(text).map_err(|msg| LoxError::CompileError(msg, line))?;
+        let (first, operands) = match words.split_first() {
+            Some(split) => split,
+            None => continue
+        };
+
+        if let Some(label) = first.strip_suffix(':') {
+            if !operands.is_empty() {
+                return Err(LoxError::CompileError(format!("expected label '{}' on a line of its own", label), line));
+            }
+            if labels.insert(label, chunk.count).is_some() {
+                return Err(LoxError::CompileError(format!("label '{}' is defined twice", label), line));
+            }
+            continue;
+        }
+
+        let op = OpCode::from_mnemonic(first)
+            .ok_or_else(|| LoxError::CompileError(format!("unknown instruction '{}'", first), line))?;
+        let info = op.info();
+        let expected = match info.operands {
+            Operands::None => 0,
+            Operands::Invoke => 2,
+            _ => 1
+        };
+        if operands.len() != expected {
+            return Err(LoxError::CompileError(format!("{} takes {} operands but got {}", info.mnemonic, expected, operands.len()), line));
+        }
+
+        chunk.write(op, line);
+        match info.operands {
+            Operands::None => (),
+            Operands::Constant => {
+                let value = constant(operands[0]).map_err(|msg| LoxError::CompileError(msg, line))?;
+                let index = add_constant(&mut chunk, value, line)?;
+                chunk.write(index, line);
+            },
+            Operands::Name => {
+                let index = add_constant(&mut chunk, name(operands[0]), line)?;
+                chunk.write(index, line);
+            },
+            Operands::Count => chunk.write(count(operands[0], line)?, line),
+            Operands::Invoke => {
+                let index = add_constant(&mut chunk, name(operands[0]), line)?;
+                chunk.write(index, line);
+                chunk.write(count(operands[1], line)?, line);
+            },
+            Operands::Jump => {
+                jumps.push((chunk.count, operands[0], line));
+                chunk.write(0xffu8, line);
+                chunk.write(0xffu8, line);
+            }
+        }
+    }
+
+    for (offset, label, line) in jumps {
+        let target = *labels.get(label)
+            .ok_or_else(|| LoxError::CompileError(format!("undefined label '{}'", label), line))?;
+        // jumps are measured from the end of the instruction, and only go forward
+        let end = offset + 2;
+        if target < end {
+            return Err(LoxError::CompileError(format!("can't jump backwards to '{}'", label), line));
+        }
+        let distance = target - end;
+        if distance > u16::MAX as usize {
+            return Err(LoxError::CompileError(format!("too much code to jump over to '{}'", label), line));
+        }
+        chunk.code[offset..end].copy_from_slice(&(distance as u16).to_be_bytes());
+    }
+
+    verify(&chunk)?;
+    Ok(chunk)
+}
+
+/// Split a line into words, keeping quoted strings whole and dropping comments
+fn words(text: &str) -> Result<Vec<&str>, String> {
+    let mut words = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() && !rest.starts_with(';') {
+        let end = if let Some(quoted) = rest.strip_prefix('"') {
+            match quoted.find('"') {
+                Some(close) => close + 2,
+                None => return Err(format!("unterminated string {}", rest))
+            }
+        }
+        else {
+            rest.find(|c: char| c.is_whitespace() || c == ';').unwrap_or(rest.len())
+        };
+        words.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    Ok(words)
+}
+
+fn constant(word: &str) -> Result<Value, String> {
+    match word {
+        "nil" => Ok(Value::Nil),
+        "true" => Ok(Value::Boolean(true)),
+        "false" => Ok(Value::Boolean(false)),
+        _ if word.starts_with('"') => Ok(Value::String(Rc::from(&word[1..word.len() - 1]))),
+        _ => literal::number(word)
+    }
+}
+
+fn name(word: &str) -> Value {
+    let name = word.strip_prefix('"').and_then(|w| w.strip_suffix('"')).unwrap_or(word);
+    Value::String(Rc::from(name))
+}
+
+fn count(word: &str, line: usize) -> Result<u8, LoxError> {
+    word.parse::<u8>()
+        .map_err(|_| LoxError::CompileError(format!("expected an argument count, found '{}'", word), line))
+}
+
+fn add_constant(chunk: &mut Chunk, value: Value, line: usize) -> Result<u8, LoxError> {
+    if chunk.constants.count == MAX_CONSTANTS {
+        return Err(LoxError::CompileError("too many constants in one chunk".to_string(), line));
+    }
+    Ok(chunk.add_constant(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> (String, usize) {
+        match assemble(source) {
+            Err(LoxError::CompileError(msg, line)) => (msg, line),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("expected {:?} not to assemble", source)
+        }
+    }
+
+    #[test]
+    fn assembles_instructions() {
+        let chunk = assemble("\
+            ; prints 3 unless it's false\n\
+            OP_CONSTANT 1\n\
+            OP_CONSTANT 2  ; the second operand\n\
+            OP_ADD\n\
+            OP_JUMP_IF_FALSE done\n\
+            OP_INVOKE \"to string\" 0\n\
+            done:\n\
+            OP_RETURN\n").unwrap();
+
+        assert_eq!(&chunk.code[..chunk.count], &[
+            OpCode::Constant as u8, 0,
+            OpCode::Constant as u8, 1,
+            OpCode::Add as u8,
+            OpCode::JumpIfFalse as u8, 0, 3,
+            OpCode::Invoke as u8, 2, 0,
+            OpCode::Return as u8,
+        ]);
+        assert_eq!(chunk.lines[4], 4);
+        assert_eq!(chunk.constants.values[1], Value::Natural(2));
+        assert_eq!(chunk.constants.values[2], Value::String(Rc::from("to string")));
+    }
+
+    #[test]
+    fn reports_mistakes_on_their_line() {
+        assert_eq!(error("OP_NIL\nOP_NOPE"), ("unknown instruction 'OP_NOPE'".to_string(), 2));
+        assert_eq!(error("OP_ADD 1"), ("OP_ADD takes 0 operands but got 1".to_string(), 1));
+        assert_eq!(error("OP_JUMP nowhere\nOP_RETURN"), ("undefined label 'nowhere'".to_string(), 1));
+        assert_eq!(error("top:\nOP_NIL\nOP_JUMP top"), ("can't jump backwards to 'top'".to_string(), 3));
+        assert_eq!(error("OP_CALL many"), ("expected an argument count, found 'many'".to_string(), 1));
+    }
+
+    #[test]
+    fn verifies_the_chunk() {
+        let (msg, _) = error("OP_ADD\nOP_RETURN");
+        assert!(msg.contains("underflow"), "{}", msg);
+    }
+}
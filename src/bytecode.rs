@@ -0,0 +1,202 @@
+//! A file format for compiled chunks, so scripts can be compiled once and
+//! run many times without being parsed again
+//!
+//! The layout is the magic bytes `\x89LOXC` and a version byte, then the
+//! constant count and each constant as a tag byte and its payload, then the
+//! code length and the code, and finally the line of each byte of code. Every
+//! multi-byte number is little endian.
+
+use std::convert::TryInto;
+use std::rc::Rc;
+
+use chunk::Chunk;
+use errors::LoxError;
+use values::{Value, ValueArray};
+use verifier::verify;
+
+/// The bytes every compiled file starts with. `0x89` can't begin valid
+/// UTF-8, so no source file is mistaken for a compiled one
+pub const MAGIC: &[u8] = b"\x89LOXC";
+// 2: OP_INVOKE takes its method name as an operand
+// 3: the constant count is a u16, since a chunk can hold 256 constants
+const VERSION: u8 = 3;
+
+const NIL: u8 = 0;
+const REAL: u8 = 1;
+const NATURAL: u8 = 2;
+const BOOLEAN: u8 = 3;
+const STRING: u8 = 4;
+
+/// Serialize `chunk`, which can only hold literal constants
+pub fn write(chunk: &Chunk) -> Result<Vec<u8>, LoxError> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+
+    bytes.extend_from_slice(&(chunk.constants.count as u16).to_le_bytes());
+    for value in &chunk.constants.values[..chunk.constants.count] {
+        match *value {
+            Value::Nil => bytes.push(NIL),
+            Value::Real(r) => {
+                bytes.push(REAL);
+                bytes.extend_from_slice(&r.to_bits().to_le_bytes());
+            },
+            Value::Natural(n) => {
+                bytes.push(NATURAL);
+                bytes.extend_from_slice(&n.to_le_bytes());
+            },
+            Value::Boolean(b) => bytes.extend_from_slice(&[BOOLEAN, b as u8]),
+            Value::String(ref s) => {
+                bytes.push(STRING);
+                bytes.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(s.as_bytes());
+            },
+            // the compiler only ever makes constants of literals, but a chunk
+            // built by hand could hold anything
+            Value::Native(_) | Value::Host(_) => return Err(error(&format!("can't write a {} constant", value.type_name())))
+        }
+    }
+
+    bytes.extend_from_slice(&(chunk.count as u32).to_le_bytes());
+    bytes.extend_from_slice(&chunk.code[..chunk.count]);
+    for &line in &chunk.lines[..chunk.count] {
+        bytes.extend_from_slice(&(line as u32).to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Whether `bytes` look like a compiled chunk rather than source
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Deserialize a chunk written by `write`, verifying it so a corrupt file
+/// can't make the VM misbehave
+pub fn read(bytes: &[u8]) -> Result<Chunk, LoxError> {
+    if !is_compiled(bytes) {
+        return Err(error("not a compiled lox file"));
+    }
+    let mut reader = Reader { bytes, offset: MAGIC.len() };
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(error(&format!("unsupported bytecode version {}, expected {}", version, VERSION)));
+    }
+
+    let count = u16::from_le_bytes(reader.array()?);
+    let mut values = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        values.push(match reader.byte()? {
+            NIL => Value::Nil,
+            REAL => Value::Real(f64::from_bits(u64::from_le_bytes(reader.array()?))),
+            NATURAL => Value::Natural(i64::from_le_bytes(reader.array()?)),
+            BOOLEAN => Value::Boolean(reader.byte()? != 0),
+            STRING => {
+                let length = reader.u32()? as usize;
+                match ::std::str::from_utf8(reader.take(length)?) {
+                    Ok(s) => Value::String(Rc::from(s)),
+                    Err(_) => return Err(error("string constant isn't valid utf-8"))
+                }
+            },
+            tag => return Err(error(&format!("unknown constant tag {}", tag)))
+        });
+    }
+
+    let length = reader.u32()? as usize;
+    let code = reader.take(length)?.to_vec();
+    let mut lines = Vec::with_capacity(length);
+    for _ in 0..length {
+        lines.push(reader.u32()? as usize);
+    }
+    if reader.offset != bytes.len() {
+        return Err(error("trailing bytes after the code"));
+    }
+
+    let chunk = Chunk::build(ValueArray::build(values), code, lines);
+    verify(&chunk)?;
+    Ok(chunk)
+}
+
+fn error(msg: &str) -> LoxError {
+    LoxError::CompileError(msg.to_string(), 0)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], LoxError> {
+        if self.bytes.len() - self.offset < length {
+            return Err(error("compiled file is truncated"));
+        }
+        self.offset += length;
+        Ok(&self.bytes[self.offset - length..self.offset])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoxError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, LoxError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoxError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::{compile, compile_with, Options};
+    use opcodes::OpCode;
+    use values::Native;
+
+    #[test]
+    fn round_trips_chunks() {
+        let options = Options { fold_constants: false, optimize: false };
+        let chunk = compile_with("(1 < 2.5) and \"a${-3}\" == \"a-3\" or nil", options).unwrap();
+        let bytes = write(&chunk).unwrap();
+        let read = read(&bytes).unwrap();
+        assert_eq!(read.code[..read.count], chunk.code[..chunk.count]);
+        assert_eq!(read.lines[..read.count], chunk.lines[..chunk.count]);
+        assert_eq!(read.constants.values[..], chunk.constants.values[..chunk.constants.count]);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let bytes = write(&compile("1 + \"a\"").unwrap()).unwrap();
+        assert!(read(b"1 + 2").is_err());
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(read(&trailing).is_err());
+
+        // source that happens to start with the letters of the magic
+        assert!(!is_compiled(b"LOXC + 1"));
+        assert!(read(b"LOXC + 1").is_err());
+
+        let mut version = bytes.clone();
+        version[MAGIC.len()] = 99;
+        assert!(read(&version).is_err());
+
+        // well formed, but the code reads a constant that isn't there
+        let missing = Chunk::build(ValueArray::init(), vec![OpCode::Constant as u8, 0, OpCode::Return as u8], vec![1, 1, 1]);
+        assert!(read(&write(&missing).unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_non_literal_constants() {
+        let mut chunk = Chunk::init();
+        let native = chunk.add_constant(Value::Native(Rc::new(Native::wrap("clock", || 0.0))));
+        chunk.write(OpCode::Constant, 1);
+        chunk.write(native, 1);
+        chunk.write(OpCode::Return, 1);
+        match write(&chunk) {
+            Err(LoxError::CompileError(msg, _)) => assert_eq!(msg, "can't write a native constant"),
+            other => panic!("{:?}", other)
+        }
+    }
+}
@@ -1,8 +1,13 @@
+use std::convert::TryFrom;
 use std::fmt;
 
-use ::opcodes::*;
+use ::json;
+use ::opcodes::{OpCode, Operands};
 use ::values::{Value, ValueArray};
 
+/// Constant operands are a single byte, so a chunk can index this many
+pub const MAX_CONSTANTS: usize = u8::MAX as usize + 1;
+
 pub struct Chunk {
     pub code: Vec<u8>,
     pub count: usize,
@@ -37,7 +42,8 @@ impl Chunk {
         }
     }
 
-    pub fn write(&mut self, byte:u8, line:usize) {
+    pub fn write<B: Into<u8>>(&mut self, byte:B, line:usize) {
+        let byte: u8 = byte.into();
         if self.capacity < self.count + 1 {
             let old_capacity = self.capacity;
             self.capacity = ::util::grow_capacity(old_capacity);
@@ -52,7 +58,14 @@ impl Chunk {
 
     pub fn add_constant(&mut self, value: Value) -> u8 {
         self.constants.write(value);
-        self.constants.count - 1
+        (self.constants.count - 1) as u8
+    }
+
+    /// Drop every instruction from `offset` onwards, keeping the allocation
+    pub fn truncate(&mut self, offset: usize) {
+        if offset < self.count {
+            self.count = offset;
+        }
     }
 
     fn simple_instruction(f: &mut fmt::Formatter, name:&str, offset:usize) -> Result<usize, fmt::Error> {
@@ -66,6 +79,33 @@ impl Chunk {
         Ok(offset + 2)
     }
 
+    /// Read the big-endian u16 operand stored at `offset`
+    pub fn read_short(&self, offset: usize) -> u16 {
+        ((self.code[offset] as u16) << 8) | self.code[offset + 1] as u16
+    }
+
+    /// Where the jump instruction at `offset` will continue execution if taken
+    pub fn jump_target(&self, offset: usize) -> usize {
+        offset + 3 + self.read_short(offset + 1) as usize
+    }
+
+    fn count_instruction(&self, f: &mut fmt::Formatter, name: &str, offset: usize) -> Result<usize, fmt::Error> {
+        writeln!(f, "{: >16} {:04}", name, self.code[offset + 1])?;
+        Ok(offset + 2)
+    }
+
+    fn invoke_instruction(&self, f: &mut fmt::Formatter, name: &str, offset: usize) -> Result<usize, fmt::Error> {
+        let constant: u8 = self.code[offset + 1];
+        let count: u8 = self.code[offset + 2];
+        writeln!(f, "{: >16} ({} args) {:04} '{}'", name, count, constant, self.constants.values[constant as usize])?;
+        Ok(offset + 3)
+    }
+
+    fn jump_instruction(&self, f: &mut fmt::Formatter, name: &str, offset: usize) -> Result<usize, fmt::Error> {
+        writeln!(f, "{: >16} {:04} -> {:04}", name, offset, self.jump_target(offset))?;
+        Ok(offset + 3)
+    }
+
     pub fn disassemble_instruction(&self, f: &mut fmt::Formatter, offset:usize) -> Result<usize, fmt::Error> {
         write!(f, "{:04} ", offset)?;
         if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
@@ -75,30 +115,65 @@ impl Chunk {
             write!(f, "{:04} ", self.lines[offset])?;
         }
 
-        let instruction = self.code[offset];
-        match instruction {
-            OP_RETURN => Self::simple_instruction(f, "OP_RETURN", offset),
-            OP_CONSTANT => self.constant_instruction(f, "OP_CONSTANT", offset),
-            OP_NEGATE => Self::simple_instruction(f, "OP_NEGATE", offset),
-            OP_ADD => Self::simple_instruction(f, "OP_ADD", offset),
-            OP_SUBTRACT => Self::simple_instruction(f, "OP_SUBTRACT", offset),
-            OP_MULTIPLY => Self::simple_instruction(f, "OP_MULTIPLY", offset),
-            OP_DIVIDE => Self::simple_instruction(f, "OP_DIVIDE", offset),
-            OP_NOT => Self::simple_instruction(f, "OP_NOT", offset),
-            OP_EQUAL => Self::simple_instruction(f, "OP_EQUAL", offset),
-            OP_NOTEQUAL => Self::simple_instruction(f, "OP_NOTEQUAL", offset),
-            OP_GREATER => Self::simple_instruction(f, "OP_GREATER", offset),
-            OP_GREATEREQUAL => Self::simple_instruction(f, "OP_GREATEREQUAL", offset),
-            OP_LESSER => Self::simple_instruction(f, "OP_LESSER", offset),
-            OP_LESSEREQUAL => Self::simple_instruction(f, "OP_LESSEREQUAL", offset),
-            _ => {
-                writeln!(f, "Unknown opcode {}", instruction)?;
-                Ok(offset + 1)
+        let instruction = match OpCode::try_from(self.code[offset]) {
+            Ok(op) => op.info(),
+            Err(byte) => {
+                writeln!(f, "Unknown opcode {}", byte)?;
+                return Ok(offset + 1);
             }
+        };
+        match instruction.operands {
+            Operands::None => Self::simple_instruction(f, instruction.mnemonic, offset),
+            Operands::Constant | Operands::Name => self.constant_instruction(f, instruction.mnemonic, offset),
+            Operands::Invoke => self.invoke_instruction(f, instruction.mnemonic, offset),
+            Operands::Jump => self.jump_instruction(f, instruction.mnemonic, offset),
+            Operands::Count => self.count_instruction(f, instruction.mnemonic, offset),
+        }
+    }
+
+    /// Where the instruction after the one at `offset` starts
+    pub fn next_instruction(&self, offset: usize) -> usize {
+        match OpCode::try_from(self.code[offset]) {
+            Ok(op) => offset + op.info().size(),
+            Err(_) => offset + 1
+        }
+    }
+
+    /// The instruction at `offset` as a JSON object, for tools: its offset,
+    /// line, mnemonic and raw operands, plus the constant it loads or the
+    /// offset it jumps to
+    pub fn describe_instruction(&self, offset: usize) -> json::Object {
+        let object = json::Object::new()
+            .number("offset", offset)
+            .number("line", self.lines[offset]);
+        let info = match OpCode::try_from(self.code[offset]) {
+            Ok(op) => op.info(),
+            Err(byte) => return object.string("op", "unknown").number("byte", byte)
+        };
+
+        let object = object.string("op", info.mnemonic);
+        match info.operands {
+            Operands::None => object.raw("operands", json::array(vec![])),
+            Operands::Constant | Operands::Name => {
+                let constant = self.code[offset + 1];
+                object.raw("operands", json::array(vec![constant.to_string()]))
+                    .string("constant", &self.constants.values[constant as usize].to_string())
+            },
+            Operands::Invoke => {
+                let constant = self.code[offset + 1];
+                let count = self.code[offset + 2];
+                object.raw("operands", json::array(vec![constant.to_string(), count.to_string()]))
+                    .string("constant", &self.constants.values[constant as usize].to_string())
+                    .number("count", count)
+            },
+            Operands::Jump => object.raw("operands", json::array(vec![self.read_short(offset + 1).to_string()]))
+                .number("target", self.jump_target(offset)),
+            Operands::Count => object.raw("operands", json::array(vec![self.code[offset + 1].to_string()]))
+                .number("count", self.code[offset + 1])
         }
     }
 
-    pub fn get_instruction(&self, offset: usize) -> Instruction {
+    pub fn get_instruction(&self, offset: usize) -> Instruction<'_> {
         Instruction {
             chunk: self,
             offset
@@ -0,0 +1,34 @@
+//! Evaluating operators on values known at compile time. Each helper returns
+//! `None` if the operation would fail at runtime, so the VM can report it
+
+use std::cmp::Ordering;
+
+use opcodes::OpCode;
+use values::Value;
+
+/// Evaluate a unary operator
+pub fn unary(op: OpCode, operand: Value) -> Option<Value> {
+    match op {
+        OpCode::Negate => (-operand).ok(),
+        OpCode::Not => (!operand).ok(),
+        OpCode::ToString => Some(operand.stringify()),
+        _ => None
+    }
+}
+
+/// Evaluate a binary operator
+pub fn binary(op: OpCode, a: Value, b: Value) -> Option<Value> {
+    match op {
+        OpCode::Add => (a + b).ok(),
+        OpCode::Subtract => (a - b).ok(),
+        OpCode::Multiply => (a * b).ok(),
+        OpCode::Divide => (a / b).ok(),
+        OpCode::Equal => Some(Value::Boolean(a == b)),
+        OpCode::NotEqual => Some(Value::Boolean(a != b)),
+        OpCode::Greater => a.partial_cmp(&b).map(|o| Value::Boolean(o == Ordering::Greater)),
+        OpCode::GreaterEqual => a.partial_cmp(&b).map(|o| Value::Boolean(o != Ordering::Less)),
+        OpCode::Lesser => a.partial_cmp(&b).map(|o| Value::Boolean(o == Ordering::Less)),
+        OpCode::LesserEqual => a.partial_cmp(&b).map(|o| Value::Boolean(o != Ordering::Greater)),
+        _ => None
+    }
+}
@@ -0,0 +1,62 @@
+use values::Value;
+
+/// Convert the text of a number literal, as accepted by the scanner, into
+/// the value it denotes. Literals with a fraction or exponent are reals,
+/// everything else is a natural
+pub fn number(text: &str) -> Result<Value, String> {
+    let digits: String = text.chars().filter(|c| *c != '_').collect();
+
+    let radix = match digits.get(..2) {
+        Some("0x") | Some("0X") => 16,
+        Some("0b") | Some("0B") => 2,
+        Some("0o") | Some("0O") => 8,
+        _ => 10
+    };
+    if radix != 10 {
+        return i64::from_str_radix(&digits[2..], radix)
+            .map(Value::Natural)
+            .map_err(|_| format!("integer literal '{}' is too large", text));
+    }
+
+    if digits.contains(['.', 'e', 'E']) {
+        return match digits.parse::<f64>() {
+            Ok(v) if v.is_finite() => Ok(Value::Real(v)),
+            Ok(_) => Err(format!("real literal '{}' is out of range", text)),
+            Err(_) => Err(format!("invalid number literal '{}'", text))
+        };
+    }
+
+    digits.parse::<i64>()
+        .map(Value::Natural)
+        .map_err(|_| format!("integer literal '{}' is too large", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn naturals() {
+        assert_eq!(number("42"), Ok(Value::Natural(42)));
+        assert_eq!(number("1_000_000"), Ok(Value::Natural(1_000_000)));
+        assert_eq!(number("0xFF"), Ok(Value::Natural(255)));
+        assert_eq!(number("0b1010"), Ok(Value::Natural(10)));
+        assert_eq!(number("0o17"), Ok(Value::Natural(15)));
+        assert_eq!(number("0x7fff_ffff_ffff_ffff"), Ok(Value::Natural(i64::MAX)));
+    }
+
+    #[test]
+    fn reals() {
+        assert_eq!(number("1.5"), Ok(Value::Real(1.5)));
+        assert_eq!(number("6.02e23"), Ok(Value::Real(6.02e23)));
+        assert_eq!(number("1e-3"), Ok(Value::Real(0.001)));
+        assert_eq!(number("2E+2"), Ok(Value::Real(200.0)));
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(number("9223372036854775808").is_err());
+        assert!(number("0x8000_0000_0000_0000").is_err());
+        assert!(number("1e400").is_err());
+    }
+}
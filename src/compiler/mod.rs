@@ -1,38 +1,94 @@
-mod token;
-mod scanner;
+pub mod token;
+pub mod scanner;
+mod parser;
+mod fold;
+pub mod literal;
 
+use chunk::Chunk;
 use errors::LoxError;
-use self::token::TokenType;
+use optimizer::optimize;
+use self::parser::Parser;
 use self::scanner::Scanner;
+use self::token::TokenType;
+use trace::Tracer;
 
 #[cfg(test)] mod tests;
 
-pub fn compile(source: &str) -> Result<(), LoxError> {
-    let mut scanner: Scanner = Scanner::init(&source);
-
-    let mut line: usize = 0;
-    loop {
-        let token = scanner.scan_token();
-        if token.line != line {
-            if cfg!(feature = "trace_scanner") {
-                print!("{:04} ", token.line);
-            }
-            line = token.line;
-        }
-        else if cfg!(feature = "trace_scanner") {
-            print!("   | ");
-        }
+/// Knobs controlling how source is turned into bytecode
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// evaluate operators on literal operands at compile time
+    pub fold_constants: bool,
+    /// run the peephole optimizer over the finished chunk
+    pub optimize: bool
+}
 
-        if cfg!(feature = "trace_scanner") {
-            println!("{}", token.token_type);
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            fold_constants: true,
+            optimize: true
         }
+    }
+}
+
+pub fn compile(source: &str) -> Result<Chunk, LoxError> {
+    compile_with(source, Options::default())
+}
+
+pub fn compile_with(source: &str, options: Options) -> Result<Chunk, LoxError> {
+    compile_traced(source, options, None)
+}
 
+/// Compile, reporting each scanned token to `tracer`
+pub fn compile_traced<'a>(source: &'a str, options: Options, tracer: Option<&'a mut dyn Tracer>) -> Result<Chunk, LoxError> {
+    let chunk = Parser::init(source, options, tracer).compile()?;
+    if options.optimize {
+        Ok(optimize(chunk))
+    }
+    else {
+        Ok(chunk)
+    }
+}
+
+/// Whether `source` stops part way through an expression, so that a REPL
+/// should read another line before compiling it: brackets or interpolations
+/// are left open, a string or block comment is unterminated, or the last
+/// token is an operator still waiting for its right operand
+pub fn is_incomplete(source: &str) -> bool {
+    let mut depth = 0i32;
+    let mut last = None;
+
+    for token in Scanner::init(source) {
         match token.token_type {
-            TokenType::Eof => break,
-            TokenType::Error(msg) => return Err(LoxError::InterpetError(msg, line)),
+            TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace => depth -= 1,
+            // a segment opening the string starts an interpolation, while one
+            // following an interpolated expression (after its `}`) continues it
+            TokenType::Interpolation(_) if token.lexeme(source).starts_with('"') => depth += 1,
+            TokenType::String(_) if !token.lexeme(source).starts_with('"') => depth -= 1,
+            TokenType::Error(ref msg, _) => return msg.starts_with("unterminated"),
+            TokenType::Comment(_) | TokenType::BlockComment(_) | TokenType::DocComment(_) => continue,
             _ => ()
         }
+        last = Some(token.token_type);
+    }
+
+    if depth != 0 {
+        // too many closing brackets won't be fixed by reading more
+        return depth > 0;
+    }
+    match last {
+        Some(TokenType::Interpolation(_)) => true,
+        Some(ref token_type) => is_operator(token_type),
+        None => false
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+fn is_operator(token_type: &TokenType) -> bool {
+    matches!(*token_type,
+        TokenType::Comma | TokenType::Dot | TokenType::Minus | TokenType::Plus |
+        TokenType::Slash | TokenType::Star | TokenType::Bang | TokenType::BangEqual |
+        TokenType::Equal | TokenType::EqualEqual | TokenType::Greater | TokenType::GreaterEqual |
+        TokenType::Less | TokenType::LessEqual | TokenType::And | TokenType::Or)
+}
@@ -0,0 +1,515 @@
+use std::convert::TryFrom;
+use std::mem;
+use std::rc::Rc;
+
+use chunk::{Chunk, MAX_CONSTANTS};
+use opcodes::OpCode;
+use values::Value;
+use errors::LoxError;
+use super::Options;
+use super::fold;
+use super::literal;
+use super::token::{Token, TokenType};
+use super::scanner::Scanner;
+use trace::Tracer;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment,  // =
+    Or,          // or
+    And,         // and
+    Equality,    // == !=
+    Comparison,  // < > <= >=
+    Term,        // + -
+    Factor,      // * /
+    Unary,       // ! -
+    Call,        // . () []
+    Primary
+}
+
+impl Precedence {
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call => Precedence::Primary,
+            Precedence::Primary => Precedence::Primary
+        }
+    }
+}
+
+fn infix_precedence(token_type: &TokenType) -> Precedence {
+    match token_type {
+        TokenType::Minus | TokenType::Plus => Precedence::Term,
+        TokenType::Slash | TokenType::Star => Precedence::Factor,
+        TokenType::BangEqual | TokenType::EqualEqual => Precedence::Equality,
+        TokenType::Greater | TokenType::GreaterEqual
+            | TokenType::Less | TokenType::LessEqual => Precedence::Comparison,
+        TokenType::And => Precedence::And,
+        TokenType::Or => Precedence::Or,
+        TokenType::LeftParen | TokenType::Dot => Precedence::Call,
+        _ => Precedence::None
+    }
+}
+
+pub struct Parser<'a> {
+    scanner: Scanner<'a>,
+    current: Token<'a>,
+    previous: Token<'a>,
+    options: Options,
+    chunk: Chunk,
+    /// the starting offset of every instruction emitted so far, so that
+    /// constant folding can look back at (and rewind) what it just wrote
+    instructions: Vec<usize>,
+    /// code before this offset may be the target of a jump, so it must not
+    /// be folded into anything that follows it
+    fold_barrier: usize,
+    /// how many instructions refer to each constant, since names and
+    /// repeated strings share one and folding mustn't drop it from under them
+    constant_uses: Vec<usize>,
+    tracer: Option<&'a mut dyn Tracer>
+}
+
+impl<'a> Parser<'a> {
+    pub fn init(source: &'a str, options: Options, tracer: Option<&'a mut dyn Tracer>) -> Parser<'a> {
+        Parser {
+            scanner: Scanner::init(source),
+            current: Token { token_type: TokenType::Eof, start: 0, length: 0, line: 0 },
+            previous: Token { token_type: TokenType::Eof, start: 0, length: 0, line: 0 },
+            options,
+            chunk: Chunk::init(),
+            instructions: Vec::new(),
+            fold_barrier: 0,
+            constant_uses: Vec::new(),
+            tracer
+        }
+    }
+
+    pub fn compile(mut self) -> Result<Chunk, LoxError> {
+        self.advance()?;
+        if self.current.token_type == TokenType::Eof {
+            self.emit_op(OpCode::Nil, self.current.line);
+        }
+        else {
+            self.expression()?;
+            // checked rather than consumed, since advancing past the end
+            // would scan (and trace) a second EOF
+            if self.current.token_type != TokenType::Eof {
+                return Err(self.error_at_current("expected end of expression"));
+            }
+        }
+        self.emit_op(OpCode::Return, self.current.line);
+
+        Ok(self.chunk)
+    }
+
+    fn advance(&mut self) -> Result<(), LoxError> {
+        loop {
+            let token = self.scanner.scan_token();
+            if let Some(ref mut tracer) = self.tracer {
+                tracer.on_token(&token);
+            }
+
+            match token.token_type {
+                TokenType::Comment(_) | TokenType::BlockComment(_) | TokenType::DocComment(_) => continue,
+                TokenType::Error(msg, _) => return Err(LoxError::InterpetError(msg, token.line)),
+                _ => {
+                    self.previous = mem::replace(&mut self.current, token);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<(), LoxError> {
+        if self.current.token_type == token_type {
+            return self.advance();
+        }
+
+        Err(self.error_at_current(msg))
+    }
+
+    fn error_at_current(&self, msg: &str) -> LoxError {
+        LoxError::CompileError(format!("{}, found {}", msg, self.current.token_type), self.current.line)
+    }
+
+    fn error_at_previous(&self, msg: &str) -> LoxError {
+        LoxError::CompileError(format!("{}, found {}", msg, self.previous.token_type), self.previous.line)
+    }
+
+    fn emit_op(&mut self, op: OpCode, line: usize) {
+        self.instructions.push(self.chunk.count);
+        self.chunk.write(op, line);
+    }
+
+    fn make_constant(&mut self, value: Value) -> Result<u8, LoxError> {
+        let existing = match value {
+            Value::String(ref s) => self.chunk.constants.values[..self.chunk.constants.count].iter()
+                .position(|v| matches!(v, Value::String(t) if t == s)),
+            _ => None
+        };
+
+        let constant = match existing {
+            Some(constant) => constant,
+            None => {
+                if self.chunk.constants.count == MAX_CONSTANTS {
+                    return Err(LoxError::CompileError("too many constants in one chunk".to_string(), self.previous.line));
+                }
+                self.constant_uses.push(0);
+                self.chunk.add_constant(value) as usize
+            }
+        };
+        self.constant_uses[constant] += 1;
+        Ok(constant as u8)
+    }
+
+    fn emit_value(&mut self, value: Value, line: usize) -> Result<(), LoxError> {
+        match value {
+            Value::Nil => self.emit_op(OpCode::Nil, line),
+            Value::Boolean(true) => self.emit_op(OpCode::True, line),
+            Value::Boolean(false) => self.emit_op(OpCode::False, line),
+            _ => {
+                let constant = self.make_constant(value)?;
+                self.emit_op(OpCode::Constant, line);
+                self.chunk.write(constant, line);
+            }
+        }
+        Ok(())
+    }
+
+    /// If the `depth`-th most recent instruction (0 being the latest) just
+    /// loads a value known at compile time, return that value
+    fn peek_constant(&self, depth: usize) -> Option<Value> {
+        if depth >= self.instructions.len() {
+            return None;
+        }
+
+        let offset = self.instructions[self.instructions.len() - 1 - depth];
+        if offset < self.fold_barrier {
+            return None;
+        }
+
+        match OpCode::try_from(self.chunk.code[offset]) {
+            Ok(OpCode::Constant) => {
+                let constant = self.chunk.code[offset + 1];
+                Some(self.chunk.constants.values[constant as usize].clone())
+            },
+            Ok(OpCode::Nil) => Some(Value::Nil),
+            Ok(OpCode::True) => Some(Value::Boolean(true)),
+            Ok(OpCode::False) => Some(Value::Boolean(false)),
+            _ => None
+        }
+    }
+
+    /// Rewind the last `count` instructions, releasing any constants they
+    /// were the sole users of
+    fn drop_instructions(&mut self, count: usize) {
+        for _ in 0..count {
+            let offset = match self.instructions.pop() {
+                Some(o) => o,
+                None => return
+            };
+            if self.chunk.code[offset] == OpCode::Constant as u8 {
+                let constant = self.chunk.code[offset + 1] as usize;
+                self.constant_uses[constant] -= 1;
+                if self.constant_uses[constant] == 0 && constant + 1 == self.chunk.constants.count {
+                    self.chunk.constants.pop();
+                    self.constant_uses.pop();
+                }
+            }
+            self.chunk.truncate(offset);
+        }
+    }
+
+    /// Emit a jump with a placeholder distance, returning the offset of the
+    /// placeholder so it can be patched once the destination is known
+    fn emit_jump(&mut self, op: OpCode, line: usize) -> usize {
+        self.emit_op(op, line);
+        self.chunk.write(0xffu8, line);
+        self.chunk.write(0xffu8, line);
+        self.chunk.count - 2
+    }
+
+    /// Point the jump whose placeholder is at `offset` at the next instruction
+    fn patch_jump(&mut self, offset: usize) -> Result<(), LoxError> {
+        let jump = self.chunk.count - offset - 2;
+        if jump > u16::MAX as usize {
+            return Err(LoxError::CompileError("too much code to jump over".to_string(), self.previous.line));
+        }
+
+        self.chunk.code[offset] = ((jump >> 8) & 0xff) as u8;
+        self.chunk.code[offset + 1] = (jump & 0xff) as u8;
+        self.fold_barrier = self.chunk.count;
+        Ok(())
+    }
+
+    fn emit_unary(&mut self, op: OpCode, line: usize) -> Result<(), LoxError> {
+        if self.options.fold_constants {
+            if let Some(operand) = self.peek_constant(0) {
+                if let Some(folded) = fold::unary(op, operand) {
+                    self.drop_instructions(1);
+                    return self.emit_value(folded, line);
+                }
+            }
+        }
+
+        self.emit_op(op, line);
+        Ok(())
+    }
+
+    fn emit_binary(&mut self, op: OpCode, line: usize) -> Result<(), LoxError> {
+        if self.options.fold_constants {
+            if let (Some(a), Some(b)) = (self.peek_constant(1), self.peek_constant(0)) {
+                if let Some(folded) = fold::binary(op, a, b) {
+                    self.drop_instructions(2);
+                    return self.emit_value(folded, line);
+                }
+            }
+        }
+
+        self.emit_op(op, line);
+        Ok(())
+    }
+
+    fn expression(&mut self) -> Result<(), LoxError> {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), LoxError> {
+        self.advance()?;
+        // `a + b = c` must not parse as `a + (b = c)`
+        let can_assign = precedence <= Precedence::Assignment;
+        self.prefix(can_assign)?;
+
+        while precedence <= infix_precedence(&self.current.token_type) {
+            self.advance()?;
+            self.infix(can_assign)?;
+        }
+
+        if can_assign && self.current.token_type == TokenType::Equal {
+            return Err(self.error_at_current("invalid assignment target"));
+        }
+        Ok(())
+    }
+
+    fn prefix(&mut self, can_assign: bool) -> Result<(), LoxError> {
+        let line = self.previous.line;
+        match self.previous.token_type {
+            TokenType::LeftParen => self.grouping(),
+            TokenType::Minus | TokenType::Bang => self.unary(),
+            TokenType::Number(text) => self.number(text, line),
+            TokenType::Nil => self.emit_value(Value::Nil, line),
+            TokenType::True => self.emit_value(Value::Boolean(true), line),
+            TokenType::False => self.emit_value(Value::Boolean(false), line),
+            TokenType::String(ref text) => {
+                let value = Value::String(Rc::from(text.as_ref()));
+                self.emit_value(value, line)
+            },
+            TokenType::Interpolation(_) => self.interpolation(),
+            TokenType::Identifier(ref name) => {
+                let name = Rc::from(name.as_ref());
+                self.variable(name, can_assign, line)
+            },
+            _ => Err(self.error_at_previous("expected expression"))
+        }
+    }
+
+    fn infix(&mut self, can_assign: bool) -> Result<(), LoxError> {
+        match self.previous.token_type {
+            TokenType::And => self.and(),
+            TokenType::Or => self.or(),
+            TokenType::LeftParen => self.call(),
+            TokenType::Dot => self.dot(can_assign),
+            _ => self.binary()
+        }
+    }
+
+    /// Read a global, or assign to it if an `=` follows
+    fn variable(&mut self, name: Rc<str>, can_assign: bool, line: usize) -> Result<(), LoxError> {
+        let constant = self.make_constant(Value::String(name))?;
+        let op = if can_assign && self.current.token_type == TokenType::Equal {
+            self.advance()?;
+            self.expression()?;
+            OpCode::SetGlobal
+        }
+        else {
+            OpCode::GetGlobal
+        };
+
+        self.emit_op(op, line);
+        self.chunk.write(constant, line);
+        Ok(())
+    }
+
+    /// Call the value just emitted with the arguments that follow
+    fn call(&mut self) -> Result<(), LoxError> {
+        let line = self.previous.line;
+        let count = self.arguments()?;
+        self.emit_op(OpCode::Call, line);
+        self.chunk.write(count, line);
+        Ok(())
+    }
+
+    /// Get, set or invoke a property of the value just emitted
+    fn dot(&mut self, can_assign: bool) -> Result<(), LoxError> {
+        let line = self.previous.line;
+        let name: Rc<str> = match self.current.token_type {
+            TokenType::Identifier(ref name) => Rc::from(name.as_ref()),
+            _ => return Err(self.error_at_current("expected property name after '.'"))
+        };
+        self.advance()?;
+
+        let constant = self.make_constant(Value::String(name))?;
+        if can_assign && self.current.token_type == TokenType::Equal {
+            self.advance()?;
+            self.expression()?;
+            self.emit_op(OpCode::SetProperty, line);
+            self.chunk.write(constant, line);
+        }
+        else if self.current.token_type == TokenType::LeftParen {
+            self.advance()?;
+            let count = self.arguments()?;
+            self.emit_op(OpCode::Invoke, line);
+            self.chunk.write(constant, line);
+            self.chunk.write(count, line);
+        }
+        else {
+            self.emit_op(OpCode::GetProperty, line);
+            self.chunk.write(constant, line);
+        }
+        Ok(())
+    }
+
+    /// Parse the arguments of a call up to and including the `)`
+    fn arguments(&mut self) -> Result<u8, LoxError> {
+        let mut count = 0;
+        if self.current.token_type != TokenType::RightParen {
+            loop {
+                if count == u8::MAX as usize {
+                    return Err(self.error_at_current("can't have more than 255 arguments"));
+                }
+                self.expression()?;
+                count += 1;
+                if self.current.token_type != TokenType::Comma {
+                    break;
+                }
+                self.advance()?;
+            }
+        }
+        self.consume(TokenType::RightParen, "expected ')' after arguments")?;
+        Ok(count as u8)
+    }
+
+    fn grouping(&mut self) -> Result<(), LoxError> {
+        self.expression()?;
+        self.consume(TokenType::RightParen, "expected ')' after expression")
+    }
+
+    fn number(&mut self, text: &str, line: usize) -> Result<(), LoxError> {
+        match literal::number(text) {
+            Ok(v) => self.emit_value(v, line),
+            Err(msg) => Err(LoxError::CompileError(msg, line))
+        }
+    }
+
+    /// Lower `"a${b}c"` into `"a" + str(b) + "c"`, skipping empty segments
+    fn interpolation(&mut self) -> Result<(), LoxError> {
+        let mut pieces = 0;
+        loop {
+            let line = self.previous.line;
+            let (segment, done): (Rc<str>, bool) = match self.previous.token_type {
+                TokenType::Interpolation(ref text) => (Rc::from(text.as_ref()), false),
+                TokenType::String(ref text) => (Rc::from(text.as_ref()), true),
+                _ => return Err(self.error_at_previous("expected '}' after interpolated expression"))
+            };
+
+            if !segment.is_empty() {
+                self.emit_value(Value::String(segment), line)?;
+                self.concatenate(&mut pieces, line)?;
+            }
+            if done {
+                return Ok(());
+            }
+
+            self.expression()?;
+            let line = self.previous.line;
+            self.emit_unary(OpCode::ToString, line)?;
+            self.concatenate(&mut pieces, line)?;
+            self.advance()?;
+        }
+    }
+
+    /// Join the piece just emitted onto the string built so far
+    fn concatenate(&mut self, pieces: &mut usize, line: usize) -> Result<(), LoxError> {
+        *pieces += 1;
+        if *pieces > 1 {
+            self.emit_binary(OpCode::Add, line)?;
+        }
+        Ok(())
+    }
+
+    fn unary(&mut self) -> Result<(), LoxError> {
+        let operator = self.previous.token_type.clone();
+        let line = self.previous.line;
+
+        self.parse_precedence(Precedence::Unary)?;
+
+        match operator {
+            TokenType::Minus => self.emit_unary(OpCode::Negate, line),
+            TokenType::Bang => self.emit_unary(OpCode::Not, line),
+            _ => unreachable!()
+        }
+    }
+
+    fn binary(&mut self) -> Result<(), LoxError> {
+        let operator = self.previous.token_type.clone();
+        let line = self.previous.line;
+
+        self.parse_precedence(infix_precedence(&operator).next())?;
+
+        let op = match operator {
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            TokenType::Less => OpCode::Lesser,
+            TokenType::LessEqual => OpCode::LesserEqual,
+            _ => unreachable!()
+        };
+        self.emit_binary(op, line)
+    }
+
+    fn and(&mut self) -> Result<(), LoxError> {
+        let line = self.previous.line;
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse, line);
+
+        self.emit_op(OpCode::Pop, line);
+        self.parse_precedence(Precedence::And)?;
+
+        self.patch_jump(end_jump)
+    }
+
+    fn or(&mut self) -> Result<(), LoxError> {
+        let line = self.previous.line;
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse, line);
+        let end_jump = self.emit_jump(OpCode::Jump, line);
+
+        self.patch_jump(else_jump)?;
+        self.emit_op(OpCode::Pop, line);
+
+        self.parse_precedence(Precedence::Or)?;
+        self.patch_jump(end_jump)
+    }
+}
@@ -1,26 +1,101 @@
+use std::borrow::Cow;
 use std::str::Chars;
 use std::iter::Peekable;
 
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use unicode_xid::UnicodeXID;
+
 use super::token::Token;
 use super::token::TokenType;
 
+/// Turns source text into tokens, either by calling `scan_token` until it
+/// returns `TokenType::Eof` or by iterating
 #[derive(Debug)]
 pub struct Scanner<'a> {
-    pub source: &'a str,
-    pub chars: Peekable<Chars<'a>>,
-    pub start: usize,
-    pub current: usize,
-    pub line: usize
+    source: &'a str,
+    // the rest of `source`, which must stay in step with `current`
+    chars: Peekable<Chars<'a>>,
+    // byte offsets into `source` of the token being scanned and the next char
+    start: usize,
+    current: usize,
+    line: usize,
+    // brace depth inside each `${...}` currently open, innermost last
+    interpolations: Vec<usize>,
+    // emit whitespace and newlines as tokens instead of skipping them
+    lossless: bool,
+    // set once the iterator has seen `Eof`
+    finished: bool
 }
 
+/// A scanning error message and the byte offset it points at
+type ScanError = (String, usize);
+
 fn is_digit(c: char) -> bool {
-    c >= '0' && c <= '9'
+    c.is_ascii_digit()
 }
 
+/// Identifiers follow the Unicode XID rules; ASCII is checked first since
+/// it's by far the most common case
 fn is_alpha(c: char) -> bool {
-       (c >= 'a' && c <= 'z')
-    || (c >= 'A' && c <= 'Z')
-    || c == '_'
+    if c.is_ascii() {
+        c.is_ascii_alphabetic() || c == '_'
+    }
+    else {
+        UnicodeXID::is_xid_start(c)
+    }
+}
+
+fn is_alphanumeric(c: char) -> bool {
+    if c.is_ascii() {
+        c.is_ascii_alphanumeric() || c == '_'
+    }
+    else {
+        UnicodeXID::is_xid_continue(c)
+    }
+}
+
+fn string_token(contents: Cow<'_, str>, interpolated: bool) -> TokenType<'_> {
+    if interpolated {
+        TokenType::Interpolation(contents)
+    }
+    else {
+        TokenType::String(contents)
+    }
+}
+
+// every reserved word is declared exactly once here; the list of keywords
+// and the lookup `Scanner::identifer` uses are both generated from it
+macro_rules! keywords {
+    ($($word:literal => $token:ident,)*) => {
+        /// Every reserved word, as recognized by `Scanner::identifer`
+        pub const KEYWORDS: &[&str] = &[$($word),*];
+
+        fn keyword(word: &str) -> Option<TokenType<'static>> {
+            match word {
+                $($word => Some(TokenType::$token),)*
+                _ => None
+            }
+        }
+    };
+}
+
+keywords! {
+    "and" => And,
+    "class" => Class,
+    "else" => Else,
+    "false" => False,
+    "for" => For,
+    "fun" => Fun,
+    "if" => If,
+    "nil" => Nil,
+    "or" => Or,
+    "print" => Print,
+    "return" => Return,
+    "super" => Super,
+    "this" => This,
+    "true" => True,
+    "var" => Var,
+    "while" => While,
 }
 
 impl<'a> Scanner<'a> {
@@ -30,7 +105,19 @@ impl<'a> Scanner<'a> {
             chars: source.chars().peekable(),
             start: 0,
             current: 0,
-            line: 1
+            line: 1,
+            interpolations: Vec::new(),
+            lossless: false,
+            finished: false
+        }
+    }
+
+    /// A scanner that also produces `Whitespace` and `Newline` tokens, so
+    /// that the tokens' spans cover every byte of the source
+    pub fn lossless(source: &'a str) -> Self {
+        Scanner {
+            lossless: true,
+            ..Scanner::init(source)
         }
     }
 
@@ -63,18 +150,24 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn error_token(&self, msg: &str) -> Token {
-        Token {
-            token_type: TokenType::Error(msg.to_string()),
-            start: self.start,
-            length: msg.len(),
-            line: self.line
-        }
+    fn error_token(&self, msg: &str) -> Token<'a> {
+        self.error_at(msg, self.start)
+    }
+
+    /// An error token spanning everything consumed so far, pointing at the
+    /// character at `offset`
+    fn error_at(&self, msg: &str, offset: usize) -> Token<'a> {
+        self.make_token(TokenType::Error(msg.to_string(), offset))
+    }
+
+    fn peek_next(&self) -> Option<char> {
+        self.chars.clone().nth(1)
     }
 
     fn advance(&mut self) -> Option<char> {
-        self.current += 1;
-        self.chars.next()
+        let c = self.chars.next()?;
+        self.current += c.len_utf8();
+        Some(c)
     }
 
     fn match_next(&mut self, c: &char) -> bool {
@@ -89,73 +182,218 @@ impl<'a> Scanner<'a> {
         matches
     }
 
-    fn string(&mut self) -> Token {
-        while let Some(c) = self.chars.peek().cloned() {
+    /// Scan string contents up to the closing quote, or up to the `${` that
+    /// starts an interpolated expression. Called after the opening quote, or
+    /// after the `}` that ends an interpolated expression
+    fn string(&mut self) -> Token<'a> {
+        let mut decoded = String::new();
+        let mut escaped = false;
+        let mut delimiter = 1;
+
+        loop {
+            let c = match self.advance() {
+                Some(c) => c,
+                None => return self.error_token("unterminated string")
+            };
+
             match c {
-                '\n' => { self.line += 1; },
-                '"' => { break; },
-                _ => { self.advance(); }
+                '"' => break,
+                '$' if self.match_next(&'{') => {
+                    self.interpolations.push(0);
+                    delimiter = 2;
+                    break;
+                },
+                '\n' => {
+                    self.line += 1;
+                    decoded.push(c);
+                },
+                '\\' => {
+                    escaped = true;
+                    let line = self.line;
+                    match self.escape() {
+                        Ok(e) => decoded.push(e),
+                        Err((msg, offset)) => {
+                            self.skip_string();
+                            // report the escape's own line, not the one the string ends on
+                            return Token { line, ..self.error_at(&msg, offset) };
+                        }
+                    }
+                },
+                _ => decoded.push(c)
             };
         }
 
-        if self.chars.peek().is_none() {
-            return self.error_token("unterminated string");
+        let interpolated = delimiter == 2;
+        if escaped {
+            return self.make_token(string_token(Cow::Owned(decoded), interpolated));
         }
 
-        // actually get the string contents!
-        let start = match self.source.char_indices().nth(self.start + 1) {
-            Some(s) => s.0,
-            None => return self.error_token("string underflow")
-        };
-        let end = match self.source.char_indices().nth(self.current - 1) {
-            Some(s) => s.0,
-            None => return self.error_token(&format!("string overflow, start: {}, current: {}, len: {}", self.start, self.current, self.source.len()))
+        // without escapes the contents can be borrowed straight from the source
+        let contents = &self.source[self.start + 1..self.current - delimiter];
+        self.make_token(string_token(Cow::Borrowed(contents), interpolated))
+    }
+
+    /// Decode the escape sequence following a backslash
+    fn escape(&mut self) -> Result<char, ScanError> {
+        let position = self.current - 1;
+        let c = match self.advance() {
+            Some(c) => c,
+            None => return Err(("unterminated string".to_string(), self.start))
         };
-        let slice: &str = &self.source[start..end+1];
+        if c == '\n' {
+            self.line += 1;
+        }
 
-        self.advance();
-        self.make_token(TokenType::String(slice))
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '$' => Ok('$'),
+            '"' => Ok('"'),
+            'u' => {
+                if !self.match_next(&'{') {
+                    return Err(("expected '{' in unicode escape".to_string(), position));
+                }
+
+                let mut digits = String::new();
+                while let Some(c) = self.chars.peek().cloned() {
+                    if !c.is_ascii_hexdigit() {
+                        break;
+                    }
+                    digits.push(c);
+                    self.advance();
+                }
+
+                if !self.match_next(&'}') || digits.is_empty() || digits.len() > 6 {
+                    return Err(("malformed unicode escape".to_string(), position));
+                }
+                u32::from_str_radix(&digits, 16).ok()
+                    .and_then(::std::char::from_u32)
+                    .ok_or_else(|| (format!("invalid unicode code point '{}'", digits), position))
+            },
+            _ => Err((format!("unknown escape sequence '\\{}'", c), position))
+        }
     }
 
-    fn number(&mut self) -> Token {
+    /// Skip past the end of a string after an error inside it
+    fn skip_string(&mut self) {
+        while let Some(c) = self.advance() {
+            match c {
+                '"' => return,
+                '\n' => self.line += 1,
+                // an escaped quote doesn't end the string
+                '\\' => {
+                    let escaped = self.advance();
+                    if escaped == Some('\n') {
+                        self.line += 1;
+                    }
+                },
+                _ => ()
+            }
+        }
+    }
+
+    /// Consume a run of digits in `radix`, allowing single underscores
+    /// between digits. `count` is the number of digits already consumed;
+    /// returns the total
+    fn digits(&mut self, radix: u32, mut count: usize) -> Result<usize, ScanError> {
+        let mut separator = false;
         while let Some(c) = self.chars.peek().cloned() {
-            if !is_digit(c) {
+            if c == '_' {
+                if count == 0 {
+                    return Err(("digit separator must follow a digit".to_string(), self.current));
+                }
+                if separator {
+                    return Err(("repeated digit separator".to_string(), self.current));
+                }
+                separator = true;
+            }
+            else if c.is_digit(radix) {
+                separator = false;
+                count += 1;
+            }
+            else {
                 break;
             }
             self.advance();
         }
 
-        let has_fractional = match self.chars.peek() {
-            Some(c) => *c == '.',
-            None => false
-        };
-        if has_fractional {
-            self.advance();
-            while let Some(c) = self.chars.peek().cloned() {
-                if !is_digit(c) {
-                    break;
+        if separator {
+            return Err(("digit separator must be followed by a digit".to_string(), self.current - 1));
+        }
+        Ok(count)
+    }
+
+    fn number(&mut self, first: char) -> Token<'a> {
+        match self.number_literal(first) {
+            Ok(token) => token,
+            Err((msg, offset)) => {
+                // swallow the rest of the malformed literal
+                while let Some(c) = self.chars.peek().cloned() {
+                    if !(is_alphanumeric(c) || c == '.') {
+                        break;
+                    }
+                    self.advance();
                 }
-                self.advance();
+                self.error_at(&msg, offset)
             }
         }
+    }
 
-        // extract the bits
-        let start = match self.source.char_indices().nth(self.start) {
-            Some(s) => s.0,
-            None => return self.error_token("number underflow")
+    fn number_literal(&mut self, first: char) -> Result<Token<'a>, ScanError> {
+        let prefix = match self.chars.peek() {
+            Some('x') | Some('X') if first == '0' => Some((16, "hex")),
+            Some('b') | Some('B') if first == '0' => Some((2, "binary")),
+            Some('o') | Some('O') if first == '0' => Some((8, "octal")),
+            _ => None
         };
-        let end = match self.source.char_indices().nth(self.current - 1) {
-            Some(s) => s.0,
-            None => return self.error_token(&format!("number overflow, start: {}, current: {}, len: {}", self.start, self.current, self.source.len()))
+
+        let kind = match prefix {
+            Some((radix, kind)) => {
+                self.advance();
+                if self.digits(radix, 0)? == 0 {
+                    return Err((format!("missing digits in {} literal", kind), self.current));
+                }
+                kind
+            },
+            None => {
+                self.digits(10, 1)?;
+
+                let has_fractional = self.chars.peek() == Some(&'.')
+                    && self.peek_next().is_some_and(is_digit);
+                if has_fractional {
+                    self.advance();
+                    self.digits(10, 0)?;
+                }
+
+                if let Some('e') | Some('E') = self.chars.peek() {
+                    self.advance();
+                    if let Some('+') | Some('-') = self.chars.peek() {
+                        self.advance();
+                    }
+                    if self.digits(10, 0)? == 0 {
+                        return Err(("missing digits in exponent".to_string(), self.current));
+                    }
+                }
+                "number"
+            }
         };
-        let slice: &str = &self.source[start..end+1];
 
-        self.make_token(TokenType::Number(slice))
+        if let Some(c) = self.chars.peek().cloned() {
+            if is_alphanumeric(c) {
+                return Err((format!("invalid digit '{}' in {} literal", c, kind), self.current));
+            }
+        }
+
+        let slice = &self.source[self.start..self.current];
+        Ok(self.make_token(TokenType::Number(slice)))
     }
 
-    fn identifer(&mut self) -> Token {
+    fn identifer(&mut self) -> Token<'a> {
         while let Some(c) = self.chars.peek().cloned() {
-            if is_digit(c) || is_alpha(c) {
+            if is_alphanumeric(c) {
                 self.advance();
             }
             else {
@@ -163,39 +401,23 @@ impl<'a> Scanner<'a> {
             }
         }
         
-        // extract the bits
-        let start = match self.source.char_indices().nth(self.start) {
-            Some(s) => s.0,
-            None => return self.error_token(&format!("identifier underflow, start: {}, current: {}, len: {}", self.start, self.current, self.source.len()))
-        };
-        let end = match self.source.char_indices().nth(self.current - 1) {
-            Some(s) => s.0,
-            None => return self.error_token(&format!("identifier overflow, start: {}, current: {}, len: {}", self.start, self.current, self.source.len()))
-        };
-        let slice: &str = &self.source[start..end+1];
-
-        self.make_token(match slice {
-            "and" => TokenType::And,
-            "class" => TokenType::Class,
-            "else" => TokenType::Else,
-            "false" => TokenType::False,
-            "for" => TokenType::For,
-            "fun" => TokenType::Fun,
-            "if" => TokenType::If,
-            "nil" => TokenType::Nil,
-            "or" => TokenType::Or,
-            "print" => TokenType::Print,
-            "return" => TokenType::Return,
-            "super" => TokenType::Super,
-            "this" => TokenType::This,
-            "true" => TokenType::True,
-            "var" => TokenType::Var,
-            "while" => TokenType::While,
-            _ => TokenType::Identifier(slice)
+        let slice = &self.source[self.start..self.current];
+
+        self.make_token(match keyword(slice) {
+            Some(token_type) => token_type,
+            // normalize so that visually identical names are the same name
+            None if slice.is_ascii() || is_nfc(slice) => TokenType::Identifier(Cow::Borrowed(slice)),
+            None => TokenType::Identifier(Cow::Owned(slice.nfc().collect()))
         })
     }
 
-    fn comment(&mut self) -> Token {
+    /// A `//` comment, or a `///` doc comment, running to the end of the line
+    fn comment(&mut self) -> Token<'a> {
+        let doc = self.chars.peek() == Some(&'/') && self.peek_next() != Some('/');
+        if doc {
+            self.advance();
+        }
+
         while let Some(c) = self.chars.peek().cloned() {
             if c != '\n' {
                 self.advance();
@@ -205,21 +427,42 @@ impl<'a> Scanner<'a> {
             }
         }
 
-        let start = match self.source.char_indices().nth(self.start + 2) {
-            Some(s) => s.0,
-            None => return self.error_token(&format!("comment underflow, start: {}, current: {}, len: {}", self.start, self.current, self.source.len()))
-        };
-        let end = match self.source.char_indices().nth(self.current - 1) {
-            Some(s) => s.0,
-            None => return self.error_token(&format!("comment overflow, start: {}, current: {}, len: {}", self.start, self.current, self.source.len()))
-        };
-        let slice: &str = &self.source[start..end+1];
+        if doc {
+            self.make_token(TokenType::DocComment(&self.source[self.start + 3..self.current]))
+        }
+        else {
+            self.make_token(TokenType::Comment(&self.source[self.start + 2..self.current]))
+        }
+    }
+
+    /// A `/* */` comment, which may contain nested block comments
+    fn block_comment(&mut self) -> Token<'a> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance() {
+                None => return self.error_token("unterminated block comment"),
+                Some('\n') => self.line += 1,
+                Some('/') if self.match_next(&'*') => depth += 1,
+                Some('*') if self.match_next(&'/') => depth -= 1,
+                _ => ()
+            }
+        }
+
+        self.make_token(TokenType::BlockComment(&self.source[self.start + 2..self.current - 2]))
+    }
 
-        self.make_token(TokenType::Comment(slice))
+    /// A run of spaces, tabs and carriage returns
+    fn whitespace(&mut self) -> Token<'a> {
+        while let Some(' ') | Some('\r') | Some('\t') = self.chars.peek() {
+            self.advance();
+        }
+        self.make_token(TokenType::Whitespace(&self.source[self.start..self.current]))
     }
 
-    pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+    pub fn scan_token(&mut self) -> Token<'a> {
+        if !self.lossless {
+            self.skip_whitespace();
+        }
 
         self.start = self.current;
 
@@ -228,8 +471,20 @@ impl<'a> Scanner<'a> {
             None => return self.make_token(TokenType::Eof)
         };
 
+        if self.lossless {
+            match c {
+                ' ' | '\r' | '\t' => return self.whitespace(),
+                '\n' => {
+                    let token = self.make_token(TokenType::Newline);
+                    self.line += 1;
+                    return token;
+                },
+                _ => ()
+            }
+        }
+
         if is_digit(c) {
-            return self.number();
+            return self.number(c);
         }
         if is_alpha(c) {
             return self.identifer();
@@ -238,8 +493,24 @@ impl<'a> Scanner<'a> {
         match c {
             '(' => self.make_token(TokenType::LeftParen),
             ')' => self.make_token(TokenType::RightParen),
-            '{' => self.make_token(TokenType::LeftBrace),
-            '}' => self.make_token(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.make_token(TokenType::LeftBrace)
+            },
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    // the end of an interpolated expression, back into the string
+                    self.interpolations.pop();
+                    self.string()
+                },
+                Some(depth) => {
+                    *depth -= 1;
+                    self.make_token(TokenType::RightBrace)
+                },
+                None => self.make_token(TokenType::RightBrace)
+            },
             ';' => self.make_token(TokenType::Semicolon),
             ',' => self.make_token(TokenType::Comma),
             '.' => self.make_token(TokenType::Dot),
@@ -275,6 +546,9 @@ impl<'a> Scanner<'a> {
             '/' => if self.match_next(&'/') {
                        self.comment()
                    }
+                   else if self.match_next(&'*') {
+                       self.block_comment()
+                   }
                    else {
                        self.make_token(TokenType::Slash)
                    },
@@ -284,4 +558,22 @@ impl<'a> Scanner<'a> {
             _ => self.error_token(&format!("unexpected character: '{}'", c))
         }
     }
-}
\ No newline at end of file
+}
+
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Token<'a>;
+
+    /// The next token, stopping (without yielding it) at `Eof`
+    fn next(&mut self) -> Option<Token<'a>> {
+        if self.finished {
+            return None;
+        }
+
+        let token = self.scan_token();
+        if token.token_type == TokenType::Eof {
+            self.finished = true;
+            return None;
+        }
+        Some(token)
+    }
+}
@@ -1,11 +1,343 @@
-use super::compile;
+use super::{compile, compile_with, is_incomplete, Options};
+use super::scanner::{Scanner, KEYWORDS};
+use super::token::TokenType;
+use errors::LoxError;
+use opcodes::OpCode;
+use values::Value;
+use vm::VM;
+use std::borrow::Cow;
 use std::fs::File;
+use std::rc::Rc;
 use std::io::prelude::*;
 
+const UNFOLDED: Options = Options { fold_constants: false, optimize: false };
+
+fn run(src: &str, options: Options) -> Value {
+    let chunk = compile_with(src, options).unwrap();
+    let mut vm = VM::init(&chunk);
+    vm.evaluate().unwrap()
+}
+
 #[test]
 fn test_arithmetic() {
     let src = "-5 + 5 * 2 / (1.05 + 1)";
-    compile(&src.to_owned()).unwrap();
+    compile(src).unwrap();
+}
+
+#[test]
+fn folds_literal_arithmetic_into_one_constant() {
+    let src = "-5 + 5 * 2 / (1.05 + 1)";
+    let folded = compile(src).unwrap();
+    assert_eq!(&folded.code[..folded.count], &[OpCode::Constant as u8, 0, OpCode::Return as u8]);
+    assert_eq!(folded.constants.count, 1);
+
+    let unfolded = compile_with(src, UNFOLDED).unwrap();
+    assert!(unfolded.count > folded.count);
+    assert_eq!(run(src, Options::default()), run(src, UNFOLDED));
+}
+
+#[test]
+fn folds_comparisons_and_logic() {
+    for src in &["1 < 2", "!(3 >= 4)", "nil == nil", "!true != false", "2 * 3 <= 6"] {
+        let folded = compile(src).unwrap();
+        assert_eq!(folded.count, 2, "{} should fold to a single literal", src);
+        assert_eq!(run(src, Options::default()), run(src, UNFOLDED));
+    }
+}
+
+#[test]
+fn does_not_fold_runtime_errors() {
+    for src in &["-true", "!1", "1 + nil", "true < 2"] {
+        let chunk = compile(src).unwrap();
+        assert!(chunk.count > 2, "{} should be left for the VM to reject", src);
+
+        let mut vm = VM::init(&chunk);
+        assert!(vm.evaluate().is_err());
+    }
+}
+
+#[test]
+fn integer_literals_are_naturals() {
+    assert_eq!(format!("{}", run("7 / 2", Options::default())), "3");
+    assert_eq!(format!("{}", run("7 / 2.0", Options::default())), "3.5");
+    assert_eq!(format!("{}", run("1 + 2.0", UNFOLDED)), "3.0");
+    assert_eq!(run("1 == 1.0", UNFOLDED), Value::Boolean(true));
+    assert!(compile("9223372036854775808").is_err());
+}
+
+#[test]
+fn number_literal_errors_name_the_literal() {
+    match compile("1 +\n9223372036854775808") {
+        Err(LoxError::CompileError(msg, line)) => {
+            assert_eq!(msg, "integer literal '9223372036854775808' is too large");
+            assert_eq!(line, 2);
+        },
+        other => panic!("unexpected {:?}", other.err())
+    }
+}
+
+#[test]
+fn a_chunk_holds_256_constants() {
+    let names = |count: usize| (0..count).map(|i| format!("v{}", i)).collect::<Vec<_>>().join(" + ");
+    assert_eq!(compile(&names(256)).unwrap().constants.count, 256);
+    match compile(&names(257)) {
+        Err(LoxError::CompileError(msg, _)) => assert_eq!(msg, "too many constants in one chunk"),
+        other => panic!("unexpected {:?}", other.err())
+    }
+}
+
+#[test]
+fn repeated_strings_share_a_constant() {
+    let chunk = compile_with("a + a + \"a\"", UNFOLDED).unwrap();
+    assert_eq!(chunk.constants.count, 1);
+
+    // folding "a" + "b" mustn't drop the constant the global still names
+    let chunk = compile("a + (\"a\" + \"b\")").unwrap();
+    assert_eq!(chunk.code[0], OpCode::GetGlobal as u8);
+    assert_eq!(chunk.constants.values[chunk.code[1] as usize], Value::String(Rc::from("a")));
+}
+
+#[test]
+fn natural_errors_happen_at_runtime() {
+    for src in &["1 / 0", "9223372036854775807 + 1", "-9223372036854775807 - 2"] {
+        let chunk = compile(src).unwrap();
+        let mut vm = VM::init(&chunk);
+        assert!(vm.evaluate().is_err(), "{} should fail at runtime", src);
+    }
+}
+
+fn scan_one(src: &str) -> TokenType<'_> {
+    Scanner::init(src).scan_token().token_type
+}
+
+#[test]
+fn scans_extended_number_literals() {
+    for src in &["0xFF", "0b1010", "0o17", "1_000_000", "6.02e23", "1e-3", "0.5E+2"] {
+        assert_eq!(scan_one(src), TokenType::Number(src));
+    }
+    assert_eq!(run("0xFF + 0b1010 + 0o17 + 1_000", UNFOLDED), Value::Natural(1280));
+    assert_eq!(run("6.02e23", UNFOLDED), Value::Real(6.02e23));
+}
+
+#[test]
+fn rejects_malformed_number_literals() {
+    let cases = [
+        ("0x", "missing digits in hex literal", 2),
+        ("0b102", "invalid digit '2' in binary literal", 4),
+        ("1__0", "repeated digit separator", 2),
+        ("10_", "digit separator must be followed by a digit", 2),
+        ("0x_1", "digit separator must follow a digit", 2),
+        ("1e", "missing digits in exponent", 2),
+        ("2.5e+", "missing digits in exponent", 5),
+        ("12abc", "invalid digit 'a' in number literal", 2),
+    ];
+    for &(src, msg, offset) in &cases {
+        let mut scanner = Scanner::init(src);
+        let token = scanner.scan_token();
+        assert_eq!(token.token_type, TokenType::Error(msg.to_string(), offset), "scanning {}", src);
+        assert_eq!(scanner.scan_token().token_type, TokenType::Eof, "rest of {} should be consumed", src);
+    }
+    assert!(compile("0x8000_0000_0000_0000").is_err());
+}
+
+#[test]
+fn scans_multi_line_strings() {
+    let mut scanner = Scanner::init("\"one\ntwo\nthree\" nil");
+    let string = scanner.scan_token();
+    assert_eq!(string.token_type, TokenType::String(Cow::Borrowed("one\ntwo\nthree")));
+    assert_eq!(string.line, 3);
+    assert_eq!(scanner.scan_token().token_type, TokenType::Nil);
+}
+
+#[test]
+fn decodes_string_escapes() {
+    let src = r#""tab\there \"quoted\" \\ nul\0 cr\r nl\n \u{1F600}""#;
+    let expected = "tab\there \"quoted\" \\ nul\0 cr\r nl\n \u{1F600}";
+    assert_eq!(scan_one(src), TokenType::String(Cow::Owned(expected.to_string())));
+    assert_eq!(run(src, UNFOLDED), Value::String(Rc::from(expected)));
+    assert_eq!(scan_one("\"\""), TokenType::String(Cow::Borrowed("")));
+}
+
+#[test]
+fn rejects_bad_escapes() {
+    let cases = [
+        (r#""ab\q" nil"#, r"unknown escape sequence '\q'", 3),
+        (r#""\u{110000}" nil"#, "invalid unicode code point '110000'", 1),
+        (r#""x\u{}" nil"#, "malformed unicode escape", 2),
+        (r#""x\u41" nil"#, "expected '{' in unicode escape", 2),
+    ];
+    for &(src, msg, offset) in &cases {
+        let mut scanner = Scanner::init(src);
+        let token = scanner.scan_token();
+        assert_eq!(token.token_type, TokenType::Error(msg.to_string(), offset), "scanning {}", src);
+        assert_eq!(scanner.scan_token().token_type, TokenType::Nil, "scanning resumes after {}", src);
+    }
+    assert_eq!(scan_one("\"open"), TokenType::Error("unterminated string".to_string(), 0));
+
+    let mut scanner = Scanner::init("\"first\nsecond \\q here\nthird\nfourth\" nil");
+    let token = scanner.scan_token();
+    assert_eq!(token.token_type, TokenType::Error(r"unknown escape sequence '\q'".to_string(), 14));
+    assert_eq!(token.line, 2);
+    assert_eq!(scanner.scan_token().line, 4, "lines are still counted past the escape");
+    match compile("\"first\nsecond \\q here\nthird\nfourth\"") {
+        Err(LoxError::InterpetError(_, line)) => assert_eq!(line, 2),
+        other => panic!("unexpected {:?}", other.err())
+    }
+}
+
+#[test]
+fn scans_interpolation_segments() {
+    let mut scanner = Scanner::init(r#""a ${ {"b${c}"} } d" nil"#);
+    let expected = vec![
+        TokenType::Interpolation(Cow::Borrowed("a ")),
+        TokenType::LeftBrace,
+        TokenType::Interpolation(Cow::Borrowed("b")),
+        TokenType::Identifier(Cow::Borrowed("c")),
+        TokenType::String(Cow::Borrowed("")),
+        TokenType::RightBrace,
+        TokenType::String(Cow::Borrowed(" d")),
+        TokenType::Nil,
+        TokenType::Eof,
+    ];
+    for token_type in expected {
+        assert_eq!(scanner.scan_token().token_type, token_type);
+    }
+    assert_eq!(scan_one(r#""\${x}""#), TokenType::String(Cow::Owned("${x}".to_string())));
+}
+
+#[test]
+fn interpolation_stringifies_values() {
+    let src = r#""sum ${1 + 2}, ${2.5 * 2} and ${nil == nil}: ${"in${"ner"}"}""#;
+    let expected = Value::String(Rc::from("sum 3, 5.0 and true: inner"));
+    assert_eq!(run(src, UNFOLDED), expected);
+    assert_eq!(run(src, Options::default()), expected);
+    assert_eq!(compile(src).unwrap().count, 3, "literal interpolation should fold to one constant");
+
+    assert_eq!(run(r#""${1}""#, UNFOLDED), Value::String(Rc::from("1")));
+    assert!(compile(r#""${1 2}""#).is_err());
+    assert!(compile(r#""${1""#).is_err());
+}
+
+#[test]
+fn scans_comments() {
+    let mut scanner = Scanner::init("// plain\n/// documented\n//// banner\n/* outer /* inner\n */ still\n*/ nil\n//");
+    let expected = vec![
+        TokenType::Comment(" plain"),
+        TokenType::DocComment(" documented"),
+        TokenType::Comment("// banner"),
+        TokenType::BlockComment(" outer /* inner\n */ still\n"),
+        TokenType::Nil,
+        TokenType::Comment(""),
+        TokenType::Eof,
+    ];
+    let mut line = 0;
+    for token_type in expected {
+        let token = scanner.scan_token();
+        assert_eq!(token.token_type, token_type);
+        line = token.line;
+    }
+    assert_eq!(line, 7);
+
+    assert_eq!(scan_one("/* open /* nested */"), TokenType::Error("unterminated block comment".to_string(), 0));
+    assert_eq!(run("1 /* two */ + /// three\n 2", UNFOLDED), Value::Natural(3));
+}
+
+#[test]
+fn scanning_test_suite() {
+    for name in &["identifiers", "keywords", "numbers", "punctuators", "strings", "whitespace"] {
+        let path = format!("test/scanning/{}.lox", name);
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+
+        // `// expect: KIND lexeme literal`, of which we check the kind and lexeme
+        let expected: Vec<String> = contents.lines()
+            .filter_map(|line| line.trim_start().strip_prefix("// expect: "))
+            .map(|expect| expect.splitn(3, ' ').take(2).collect::<Vec<_>>().join(" "))
+            .collect();
+
+        let mut scanner = Scanner::init(&contents);
+        let mut scanned = Vec::new();
+        loop {
+            let token = scanner.scan_token();
+            if let TokenType::Comment(_) = token.token_type {
+                continue;
+            }
+            let lexeme = &contents[token.start..token.start + token.length];
+            scanned.push(format!("{} {}", token.token_type.name(), lexeme));
+            if token.token_type == TokenType::Eof {
+                break;
+            }
+        }
+        assert_eq!(scanned, expected, "scanning {}", path);
+    }
+}
+
+#[test]
+fn scans_unicode_identifiers() {
+    for name in &["café", "naïve", "π", "変数", "_ñ1", "Ωmega_2"] {
+        assert_eq!(scan_one(name), TokenType::Identifier(Cow::Borrowed(name)));
+    }
+
+    // "e" followed by a combining acute accent normalizes to a single "é"
+    let decomposed = "cafe\u{301}";
+    assert_eq!(scan_one(decomposed), TokenType::Identifier(Cow::Owned("café".to_string())));
+    assert_eq!(scan_one(decomposed), scan_one("café"));
+
+    // not identifier characters
+    assert!(matches!(scan_one("€uro"), TokenType::Error(..)));
+    assert!(matches!(scan_one("\u{301}e"), TokenType::Error(..)));
+    assert!(matches!(scan_one("1π"), TokenType::Error(..)));
+}
+
+#[test]
+fn scanner_iterates_until_eof() {
+    let types: Vec<_> = Scanner::init("1 + 2").map(|token| token.token_type).collect();
+    assert_eq!(types, vec![TokenType::Number("1"), TokenType::Plus, TokenType::Number("2")]);
+    assert_eq!(Scanner::init("").count(), 0);
+
+    let mut scanner = Scanner::init("nil");
+    assert!(scanner.next().is_some());
+    assert!(scanner.next().is_none());
+    assert!(scanner.next().is_none());
+}
+
+#[test]
+fn lossless_tokens_reproduce_the_source() {
+    let sources = [
+        "var café = \"a ${1 +\t2} b\";\r\n  // done\n/* block\n */ print 0x_1 \"\\q\" @",
+        "  \n\n\t",
+        "",
+    ];
+    for src in &sources {
+        let text: String = Scanner::lossless(src)
+            .map(|token| &src[token.start..token.start + token.length])
+            .collect();
+        assert_eq!(&text, src);
+    }
+
+    let tokens: Vec<_> = Scanner::lossless("a \n\tb").collect();
+    let types: Vec<_> = tokens.iter().map(|token| token.token_type.clone()).collect();
+    assert_eq!(types, vec![
+        TokenType::Identifier(Cow::Borrowed("a")),
+        TokenType::Whitespace(" "),
+        TokenType::Newline,
+        TokenType::Whitespace("\t"),
+        TokenType::Identifier(Cow::Borrowed("b")),
+    ]);
+    assert_eq!(tokens[2].line, 1);
+    assert_eq!(tokens[4].line, 2);
+}
+
+#[test]
+fn tokens_know_their_lexeme_and_column() {
+    let src = "var é = 1;\n  \"two\"";
+    let tokens: Vec<_> = Scanner::init(src).collect();
+    let positions: Vec<_> = tokens.iter()
+        .map(|token| (token.lexeme(src), token.line, token.column(src)))
+        .collect();
+    assert_eq!(positions, vec![
+        ("var", 1, 1), ("é", 1, 5), ("=", 1, 7), ("1", 1, 9), (";", 1, 10), ("\"two\"", 2, 3),
+    ]);
 }
 
 #[test]
@@ -13,5 +345,29 @@ fn scan_benchmark_binary_trees() {
     let mut f = File::open("test/benchmark/binary_trees.lox").unwrap();
     let mut contents = String::new();
     f.read_to_string(&mut contents).unwrap();
-    compile(&contents).unwrap();
+
+    for token in Scanner::init(&contents) {
+        if let TokenType::Error(msg, _) = token.token_type {
+            panic!("{}", msg);
+        }
+    }
+}
+
+#[test]
+fn detects_incomplete_input() {
+    for src in &["(1 +", "1 +", "-", "\"abc", "\"a${1 +", "\"a${(1}", "1 /* more", "{", "1 and"] {
+        assert!(is_incomplete(src), "{:?} should be incomplete", src);
+    }
+    for src in &["", "1 + 2", "(1)", "\"a${1}b\"", "\"a${1}b${2}c\"", "1 // +", "1)", "1 @", "}"] {
+        assert!(!is_incomplete(src), "{:?} should be complete", src);
+    }
+}
+
+#[test]
+fn keywords_scan_as_keywords() {
+    for keyword in KEYWORDS.iter() {
+        let token = Scanner::init(keyword).next().unwrap();
+        assert!(token.token_type.is_keyword(), "{} didn't scan as a keyword", keyword);
+        assert_eq!(token.token_type.name(), keyword.to_uppercase());
+    }
 }
@@ -1,5 +1,7 @@
+use std::borrow::Cow;
 use std::fmt;
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType<'a> {
     // Single-character tokens.
     LeftParen, RightParen,
@@ -14,7 +16,14 @@ pub enum TokenType<'a> {
     Less, LessEqual,
 
     // Literals.
-    Identifier(&'a str), String(&'a str), Number(&'a str),
+    Number(&'a str),
+    // NFC normalized, so only borrowed from the source if it already was
+    Identifier(Cow<'a, str>),
+    // the decoded contents, borrowed from the source when there were no escapes
+    String(Cow<'a, str>),
+    // a string segment ending in `${`, followed by the interpolated
+    // expression's tokens and then the rest of the string
+    Interpolation(Cow<'a, str>),
 
     // Keywords.
     And, Class, Else, False,
@@ -23,10 +32,20 @@ pub enum TokenType<'a> {
     True, Var, While,
 
     Comment(&'a str),
-    Error(String),
+    BlockComment(&'a str),
+    // a `///` comment documenting the declaration after it
+    DocComment(&'a str),
+    // trivia, only produced by a lossless scanner
+    Whitespace(&'a str),
+    Newline,
+
+    // the message, and the byte offset of the character it's about
+    Error(String, usize),
     Eof,
 }
 
+/// A token and where it came from; `start` and `length` are in bytes
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token<'a> {
     pub token_type: TokenType<'a>,
     pub start: usize,
@@ -34,6 +53,82 @@ pub struct Token<'a> {
     pub line: usize
 }
 
+impl<'a> Token<'a> {
+    /// The source text this token was scanned from
+    pub fn lexeme<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.start..self.start + self.length]
+    }
+
+    /// The 1-based column, in characters, where this token starts
+    pub fn column(&self, source: &str) -> usize {
+        source[..self.start].chars().rev().take_while(|c| *c != '\n').count() + 1
+    }
+}
+
+impl<'a> TokenType<'a> {
+    /// Whether this is one of the reserved words
+    pub fn is_keyword(&self) -> bool {
+        matches!(*self,
+            TokenType::And | TokenType::Class | TokenType::Else | TokenType::False |
+            TokenType::Fun | TokenType::For | TokenType::If | TokenType::Nil | TokenType::Or |
+            TokenType::Print | TokenType::Return | TokenType::Super | TokenType::This |
+            TokenType::True | TokenType::Var | TokenType::While)
+    }
+
+    /// The kind of token, in the upper snake case used by the test suite's
+    /// `// expect:` lines
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenType::LeftParen => "LEFT_PAREN",
+            TokenType::RightParen => "RIGHT_PAREN",
+            TokenType::LeftBrace => "LEFT_BRACE",
+            TokenType::RightBrace => "RIGHT_BRACE",
+            TokenType::Comma => "COMMA",
+            TokenType::Dot => "DOT",
+            TokenType::Minus => "MINUS",
+            TokenType::Plus => "PLUS",
+            TokenType::Semicolon => "SEMICOLON",
+            TokenType::Slash => "SLASH",
+            TokenType::Star => "STAR",
+            TokenType::Bang => "BANG",
+            TokenType::BangEqual => "BANG_EQUAL",
+            TokenType::Equal => "EQUAL",
+            TokenType::EqualEqual => "EQUAL_EQUAL",
+            TokenType::Greater => "GREATER",
+            TokenType::GreaterEqual => "GREATER_EQUAL",
+            TokenType::Less => "LESS",
+            TokenType::LessEqual => "LESS_EQUAL",
+            TokenType::Identifier(_) => "IDENTIFIER",
+            TokenType::Number(_) => "NUMBER",
+            TokenType::String(_) => "STRING",
+            TokenType::Interpolation(_) => "INTERPOLATION",
+            TokenType::And => "AND",
+            TokenType::Class => "CLASS",
+            TokenType::Else => "ELSE",
+            TokenType::False => "FALSE",
+            TokenType::Fun => "FUN",
+            TokenType::For => "FOR",
+            TokenType::If => "IF",
+            TokenType::Nil => "NIL",
+            TokenType::Or => "OR",
+            TokenType::Print => "PRINT",
+            TokenType::Return => "RETURN",
+            TokenType::Super => "SUPER",
+            TokenType::This => "THIS",
+            TokenType::True => "TRUE",
+            TokenType::Var => "VAR",
+            TokenType::While => "WHILE",
+            TokenType::Comment(_) => "COMMENT",
+            TokenType::BlockComment(_) => "BLOCK_COMMENT",
+            TokenType::DocComment(_) => "DOC_COMMENT",
+            TokenType::Whitespace(_) => "WHITESPACE",
+            TokenType::Newline => "NEWLINE",
+            TokenType::Error(..) => "ERROR",
+            TokenType::Eof => "EOF",
+        }
+    }
+}
+
 impl<'a> fmt::Display for TokenType<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -58,6 +153,7 @@ impl<'a> fmt::Display for TokenType<'a> {
             TokenType::LessEqual => write!(f, "<="),
             TokenType::Identifier(name) => write!(f, "identifier: {}", name),
             TokenType::String(contents) => write!(f, "string: {}", contents),
+            TokenType::Interpolation(contents) => write!(f, "interpolation: {}", contents),
             TokenType::Number(value) => write!(f, "number: {}", value),
             TokenType::And => write!(f, "and"),
             TokenType::Class => write!(f, "class"),
@@ -76,7 +172,11 @@ impl<'a> fmt::Display for TokenType<'a> {
             TokenType::Var => write!(f, "var"),
             TokenType::While => write!(f, "while"),
             TokenType::Comment(note) => write!(f, "comment: {}", note),
-            TokenType::Error(msg) => write!(f, "error: {}", msg),
+            TokenType::BlockComment(note) => write!(f, "block comment: {}", note),
+            TokenType::DocComment(note) => write!(f, "doc comment: {}", note),
+            TokenType::Whitespace(_) => write!(f, "whitespace"),
+            TokenType::Newline => write!(f, "newline"),
+            TokenType::Error(msg, _) => write!(f, "error: {}", msg),
             TokenType::Eof => write!(f, "eof"),
         }
     }
@@ -0,0 +1,110 @@
+//! Line editing for the REPL: completion of keywords, globals and commands, and
+//! input colored by the kind of each token
+
+use std::borrow::Cow;
+
+use rustyline::Helper;
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::Context;
+
+use rustilox::{Scanner, TokenType, KEYWORDS};
+
+const RESET: &str = "\x1b[0m";
+
+pub struct LoxHelper {
+    /// the REPL's `:` commands
+    commands: Vec<&'static str>,
+    /// the globals defined in the session so far
+    globals: Vec<String>
+}
+
+impl LoxHelper {
+    pub fn init(commands: Vec<&'static str>) -> LoxHelper {
+        LoxHelper {
+            commands,
+            globals: Vec::new()
+        }
+    }
+
+    pub fn set_globals(&mut self, globals: Vec<String>) {
+        self.globals = globals;
+    }
+}
+
+/// The ANSI color for a kind of token, if it isn't left plain
+fn color(token_type: &TokenType) -> Option<&'static str> {
+    match *token_type {
+        TokenType::Number(_) => Some("\x1b[33m"),
+        TokenType::String(_) | TokenType::Interpolation(_) => Some("\x1b[32m"),
+        TokenType::Comment(_) | TokenType::BlockComment(_) | TokenType::DocComment(_) => Some("\x1b[90m"),
+        TokenType::Error(_, _) => Some("\x1b[31m"),
+        ref keyword if keyword.is_keyword() => Some("\x1b[35m"),
+        _ => None
+    }
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+
+        let candidates = if start == 0 && word.starts_with(':') {
+            self.commands.iter().filter(|command| command.starts_with(word)).map(|command| command.to_string()).collect()
+        }
+        else if word.is_empty() {
+            vec![]
+        }
+        else {
+            KEYWORDS.iter().cloned()
+                .chain(self.globals.iter().map(String::as_str))
+                .filter(|name| name.starts_with(word))
+                .map(str::to_string)
+                .collect()
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Highlighter for LoxHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if line.starts_with(':') {
+            return Cow::Borrowed(line);
+        }
+
+        let mut colored = String::with_capacity(line.len() * 2);
+        let mut end = 0;
+        for token in Scanner::lossless(line) {
+            let lexeme = token.lexeme(line);
+            match color(&token.token_type) {
+                Some(color) => {
+                    colored.push_str(color);
+                    colored.push_str(lexeme);
+                    colored.push_str(RESET);
+                },
+                None => colored.push_str(lexeme)
+            }
+            end = token.start + token.length;
+        }
+        // whatever the scanner gave up on, such as an unterminated string
+        colored.push_str(&line[end..]);
+        Cow::Owned(colored)
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        kind != CmdKind::MoveCursor
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
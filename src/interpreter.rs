@@ -1,9 +1,49 @@
+use std::io;
+use std::io::Write;
+
 use values::Value;
 use errors::LoxError;
-use compiler::compile;
+use chunk::Chunk;
+use compiler::{compile_traced, Options};
+use trace::{Tracer, TextTracer, TraceFlags};
+use vm::VM;
 
 pub fn interpret(source: &str) -> Result<Value, LoxError> {
-    compile(&source)?;
+    // the old compile-time tracing features still work, now going to stderr
+    let flags = TraceFlags::from_features();
+    if flags.any() {
+        let mut tracer = TextTracer::init(Box::new(io::stderr()), flags);
+        return interpret_traced(source, &mut tracer);
+    }
+
+    interpret_with(source, Box::new(io::stdout()), Box::new(io::stderr()))
+}
+
+/// Interpret `source`, writing the program's output to `output` and
+/// reporting runtime errors to `diagnostics`
+pub fn interpret_with(source: &str, output: Box<dyn Write>, diagnostics: Box<dyn Write>) -> Result<Value, LoxError> {
+    let chunk = compile_traced(source, Options::default(), None)?;
+    let mut vm = VM::init(&chunk);
+    vm.set_output(output);
+    vm.set_diagnostics(diagnostics);
+    vm.evaluate()
+}
+
+/// Interpret `source`, reporting what the compiler and VM do to `tracer`
+pub fn interpret_traced(source: &str, tracer: &mut dyn Tracer) -> Result<Value, LoxError> {
+    let chunk = compile_traced(source, Options::default(), Some(&mut *tracer))?;
+    let mut vm = VM::init(&chunk);
+    vm.set_diagnostics(Box::new(io::stderr()));
+    vm.set_tracer(tracer);
+    vm.evaluate()
+}
 
-    Ok(Value::Nil)
-}
\ No newline at end of file
+/// Run an already compiled chunk, such as one read by `bytecode::read`
+pub fn run<'a>(chunk: &'a Chunk, tracer: Option<&'a mut dyn Tracer>) -> Result<Value, LoxError> {
+    let mut vm = VM::init(chunk);
+    vm.set_diagnostics(Box::new(io::stderr()));
+    if let Some(tracer) = tracer {
+        vm.set_tracer(tracer);
+    }
+    vm.evaluate()
+}
@@ -0,0 +1,162 @@
+//! Just enough JSON output for tooling to consume, without pulling in a
+//! serialization framework
+
+use std::fmt;
+use std::fmt::Write;
+
+/// Quote and escape `s` as a JSON string
+pub fn string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(quoted, "\\u{:04x}", c as u32); },
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A JSON array of already-encoded values
+pub fn array<I: IntoIterator<Item = String>>(items: I) -> String {
+    let items: Vec<String> = items.into_iter().collect();
+    format!("[{}]", items.join(","))
+}
+
+/// A JSON object, built up one field at a time and written on one line
+#[derive(Debug, Clone, Default)]
+pub struct Object {
+    fields: Vec<(&'static str, String)>
+}
+
+impl Object {
+    pub fn new() -> Object {
+        Object::default()
+    }
+
+    pub fn string(mut self, key: &'static str, value: &str) -> Object {
+        self.fields.push((key, string(value)));
+        self
+    }
+
+    pub fn number<N: fmt::Display>(mut self, key: &'static str, value: N) -> Object {
+        self.fields.push((key, value.to_string()));
+        self
+    }
+
+    pub fn boolean(mut self, key: &'static str, value: bool) -> Object {
+        self.fields.push((key, value.to_string()));
+        self
+    }
+
+    /// A field whose value is already encoded JSON
+    pub fn raw(mut self, key: &'static str, json: String) -> Object {
+        self.fields.push((key, json));
+        self
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", string(key), value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// The undecoded text of the value stored under `key` in a JSON object,
+/// looking only at the object's own fields and not inside nested values
+pub fn field<'a>(object: &'a str, key: &str) -> Option<&'a str> {
+    let wanted = string(key);
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    // where the current key (or value, once a `:` is seen) starts
+    let mut start = 0;
+    let mut value = None;
+
+    for (i, c) in object.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            }
+            else if c == '\\' {
+                escaped = true;
+            }
+            else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => {
+                depth += 1;
+                if depth == 1 {
+                    start = i + 1;
+                }
+            },
+            '}' | ']' => {
+                if depth == 1 {
+                    return value.map(|v| object[v..i].trim());
+                }
+                depth -= 1;
+            },
+            ':' if depth == 1 && object[start..i].trim() == wanted => value = Some(i + 1),
+            ',' if depth == 1 => {
+                if let Some(v) = value {
+                    return Some(object[v..i].trim());
+                }
+                start = i + 1;
+            },
+            _ => ()
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("plain"), "\"plain\"");
+        assert_eq!(string("a \"b\" \\ c\n\t\u{1}"), "\"a \\\"b\\\" \\\\ c\\n\\t\\u0001\"");
+        assert_eq!(string("café"), "\"café\"");
+    }
+
+    #[test]
+    fn writes_objects() {
+        let object = Object::new()
+            .string("kind", "NUMBER")
+            .number("line", 3)
+            .boolean("ok", true)
+            .raw("span", array(vec!["0".to_string(), "2".to_string()]));
+        assert_eq!(object.to_string(), r#"{"kind":"NUMBER","line":3,"ok":true,"span":[0,2]}"#);
+        assert_eq!(Object::new().to_string(), "{}");
+    }
+
+    #[test]
+    fn finds_fields() {
+        let object = r#"{"op":"OP_ADD","stack":["a,b","{c}"],"nested":{"op":1},"line":3}"#;
+        assert_eq!(field(object, "op"), Some(r#""OP_ADD""#));
+        assert_eq!(field(object, "stack"), Some(r#"["a,b","{c}"]"#));
+        assert_eq!(field(object, "nested"), Some(r#"{"op":1}"#));
+        assert_eq!(field(object, "line"), Some("3"));
+        assert_eq!(field(object, "missing"), None);
+        assert_eq!(field("{}", "op"), None);
+    }
+}
@@ -1,17 +1,49 @@
 // TODO: remove this when finished!
 #![allow(dead_code)]
 
+extern crate unicode_normalization;
+extern crate unicode_xid;
+
 mod chunk;
 mod opcodes;
+mod verifier;
+mod optimizer;
 mod values;
 mod vm;
 mod util;
 mod errors;
 mod interpreter;
+mod lox;
 mod compiler;
+mod trace;
+pub mod json;
+pub mod bytecode;
+pub mod assembler;
 
-pub use interpreter::interpret;
-pub use values::Value;
+pub use interpreter::{interpret, interpret_with, interpret_traced, run};
+pub use chunk::Chunk;
+pub use lox::Lox;
+pub use trace::{Tracer, TextTracer, JsonTracer, TraceFlags, Divergence};
+pub use trace::diff as trace_diff;
+pub use values::{Value, Native, IntoNative, HostObject, List, LoxClass, LoxMethods, Instance, FromLox, IntoLox, IntoLoxResult, ConversionError};
 pub use errors::LoxError;
+pub use compiler::{compile, is_incomplete};
+pub use compiler::scanner::{Scanner, KEYWORDS};
+pub use compiler::token::{Token, TokenType};
+
+/// Internals exposed for `benches/`; not a stable API
+#[doc(hidden)]
+pub mod bench {
+    pub use chunk::Chunk;
+    pub use opcodes::OpCode;
+    pub use values::ValueArray;
+    pub use vm::VM;
+}
+
+/// Internals used by the code `rustilox_derive` generates; not a stable API
+#[doc(hidden)]
+pub mod derive {
+    pub use values::native::check_arity;
+}
 
 #[cfg(test)] mod tests;
\ No newline at end of file
@@ -0,0 +1,294 @@
+//! An interpreter that outlives a single script, for hosting Lox in Rust
+//! programs and for the REPL
+
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+use compiler::{compile_traced, Options};
+use errors::LoxError;
+use trace::Tracer;
+use values::{Value, Native, IntoNative, LoxMethods};
+use vm::{Globals, VM};
+
+/// A long-lived interpreter: globals defined by the host or by one call to
+/// `eval` are still there for the next
+pub struct Lox {
+    globals: Globals,
+    output: Box<dyn Write>,
+    diagnostics: Box<dyn Write>,
+    tracer: Option<Box<dyn Tracer>>
+}
+
+impl Default for Lox {
+    fn default() -> Lox {
+        Lox::init()
+    }
+}
+
+impl Lox {
+    /// An interpreter with no globals, writing its output to stdout and
+    /// reporting runtime errors to stderr
+    pub fn init() -> Lox {
+        Lox {
+            globals: Globals::new(),
+            output: Box::new(io::stdout()),
+            diagnostics: Box::new(io::stderr()),
+            tracer: None
+        }
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    pub fn set_diagnostics(&mut self, diagnostics: Box<dyn Write>) {
+        self.diagnostics = diagnostics;
+    }
+
+    /// Report what the compiler and VM do during every later `eval` to `tracer`
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Compile and run `source`, returning the value it evaluates to
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        let chunk = match self.tracer {
+            Some(ref mut tracer) => compile_traced(source, Options::default(), Some(tracer.as_mut()))?,
+            None => compile_traced(source, Options::default(), None)?
+        };
+        let mut vm = VM::init(&chunk);
+        vm.set_output(Box::new(&mut self.output));
+        vm.set_diagnostics(Box::new(&mut self.diagnostics));
+        vm.set_globals(::std::mem::take(&mut self.globals));
+        if let Some(ref mut tracer) = self.tracer {
+            vm.set_tracer(tracer.as_mut());
+        }
+
+        let result = vm.evaluate();
+        self.globals = vm.take_globals();
+        result
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).cloned()
+    }
+
+    /// Define `name`, or change its value if it's already defined
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(Rc::from(name), value);
+    }
+
+    /// Every global and its value, sorted by name
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        let mut globals: Vec<(&str, &Value)> = self.globals.iter()
+            .map(|(name, value)| (name.as_ref(), value))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        globals
+    }
+
+    /// Define a global native called `name` that runs `function`, which can
+    /// be a plain Rust function such as `f64::hypot`
+    pub fn define<Args, F: IntoNative<Args>>(&mut self, name: &str, function: F) {
+        self.define_native(Native::wrap(name, function));
+    }
+
+    /// Define a global for an already built native, such as a variadic one
+    pub fn define_native(&mut self, native: Native) {
+        let name = Rc::from(native.name.as_str());
+        self.globals.insert(name, Value::Native(Rc::new(native)));
+    }
+
+    /// Define a global constructor for the class `T`
+    pub fn define_class<T: LoxMethods>(&mut self) {
+        self.define_native(T::constructor());
+    }
+
+    /// Call a native value from Rust
+    pub fn call(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value, LoxError> {
+        match *callee {
+            Value::Native(ref native) => native.call(args),
+            _ => Err(LoxError::RuntimeError("can only call functions".to_string(), 0))
+        }
+    }
+
+    /// Forget every global
+    pub fn reset(&mut self) {
+        self.globals.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::convert::TryFrom;
+    use values::{HostObject, FromLox};
+
+    /// An interpreter that throws away its output and error reports
+    fn quiet() -> Lox {
+        let mut lox = Lox::init();
+        lox.set_output(Box::new(io::sink()));
+        lox.set_diagnostics(Box::new(io::sink()));
+        lox
+    }
+
+    /// The message and line of the runtime error evaluating `source` fails with
+    fn runtime_error(lox: &mut Lox, source: &str) -> (String, usize) {
+        match lox.eval(source) {
+            Err(LoxError::RuntimeError(msg, line)) => (msg, line),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    /// A read-only file held in memory
+    struct File {
+        contents: String,
+        position: Cell<usize>
+    }
+
+    impl HostObject for File {
+        fn type_name(&self) -> &'static str {
+            "File"
+        }
+
+        fn get(&self, name: &str) -> Result<Value, LoxError> {
+            match name {
+                "position" => Ok(Value::Natural(self.position.get() as i64)),
+                "size" => Ok(Value::Natural(self.contents.len() as i64)),
+                _ => Err(LoxError::RuntimeError(format!("undefined property '{}' on File", name), 0))
+            }
+        }
+
+        fn set(&self, name: &str, value: Value) -> Result<(), LoxError> {
+            match name {
+                "position" => match usize::try_from(i64::from_lox(value)?) {
+                    Ok(position) => {
+                        self.position.set(position);
+                        Ok(())
+                    },
+                    Err(_) => Err(LoxError::RuntimeError("position can't be negative".to_string(), 0))
+                },
+                _ => Err(LoxError::RuntimeError(format!("can't set property '{}' on File", name), 0))
+            }
+        }
+
+        fn invoke(&self, name: &str, args: Vec<Value>) -> Result<Value, LoxError> {
+            match (name, args.len()) {
+                ("read", 0) => {
+                    let rest = self.contents.get(self.position.get()..).unwrap_or("");
+                    self.position.set(self.contents.len());
+                    Ok(Value::String(Rc::from(rest)))
+                },
+                ("read", n) => Err(LoxError::RuntimeError(format!("expected 0 arguments but got {}", n), 0)),
+                _ => Err(LoxError::RuntimeError(format!("undefined method '{}' on File", name), 0))
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_globals_between_evals() {
+        let mut lox = quiet();
+
+        lox.set_global("answer", Value::Natural(41));
+        assert_eq!(lox.eval("answer = answer + 1").unwrap(), Value::Natural(42));
+        assert_eq!(lox.eval("\"${answer}!\"").unwrap(), Value::String(Rc::from("42!")));
+        assert_eq!(lox.get_global("answer"), Some(Value::Natural(42)));
+
+        // assignment only changes globals that already exist
+        assert!(lox.eval("question = 1").is_err());
+        assert_eq!(lox.get_global("question"), None);
+        assert!(lox.eval("question").is_err());
+
+        // a runtime error part way through keeps what was already assigned
+        lox.set_global("a", Value::Nil);
+        assert!(lox.eval("(a = 1) + nil").is_err());
+        assert_eq!(lox.globals(), vec![("a", &Value::Natural(1)), ("answer", &Value::Natural(42))]);
+
+        lox.reset();
+        assert!(lox.globals().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_assignment_targets() {
+        let mut lox = quiet();
+        lox.set_global("a", Value::Nil);
+        lox.set_global("b", Value::Nil);
+        assert!(lox.eval("a + b = 1").is_err());
+        assert!(lox.eval("1 = 2").is_err());
+        assert_eq!(lox.eval("a = b = 3").unwrap(), Value::Natural(3));
+        assert_eq!(lox.get_global("a"), Some(Value::Natural(3)));
+    }
+
+    #[test]
+    fn calls_natives() {
+        let mut lox = quiet();
+        lox.define("hypot", f64::hypot);
+        lox.define("shout", |s: String, times: Option<i64>| s.to_uppercase().repeat(times.unwrap_or(1) as usize));
+        lox.define_native(Native::variadic("sum", |xs: Vec<i64>| xs.iter().sum::<i64>()));
+
+        assert_eq!(lox.eval("hypot(3, 4) + 1").unwrap(), Value::Real(6.0));
+        assert_eq!(lox.eval("shout(\"a\", 3)").unwrap(), Value::String(Rc::from("AAA")));
+        assert_eq!(lox.eval("shout(\"a\", nil)").unwrap(), Value::String(Rc::from("A")));
+        assert_eq!(lox.eval("sum() + sum(1, 2, 3)").unwrap(), Value::Natural(6));
+        assert_eq!(lox.eval("hypot").unwrap().to_string(), "<native fn hypot>");
+
+        // vecs and tuples cross over as lists
+        lox.define("split", |s: String| s.split(',').map(str::to_string).collect::<Vec<_>>());
+        lox.define("pair", || (2i64, 3i64));
+        lox.define("total", |xs: Vec<i64>, (a, b): (i64, i64)| xs.iter().sum::<i64>() + a * b);
+        assert_eq!(lox.eval("split(\"a,b\").length + split(\"c\").length").unwrap(), Value::Natural(3));
+        assert_eq!(lox.eval("split(\"a,b\").get(1)").unwrap(), Value::String(Rc::from("b")));
+        assert_eq!(lox.eval("total(pair(), pair())").unwrap(), Value::Natural(11));
+
+        let hypot = lox.get_global("hypot").unwrap();
+        assert_eq!(lox.call(&hypot, vec![Value::Real(5.0), Value::Natural(12)]).unwrap(), Value::Real(13.0));
+
+        assert_eq!(runtime_error(&mut lox, "hypot(1)"), ("expected 2 arguments but got 1".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "\nhypot(1, true)"), ("expected number, got bool".to_string(), 2));
+        assert_eq!(runtime_error(&mut lox, "sum(1, 1.5)"), ("expected integer, got number".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "1(2)"), ("can only call functions".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "total(1, pair())"), ("expected list, got number".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "split(\"a\").get(1)"), ("list index 1 is out of range".to_string(), 1));
+        assert!(lox.eval("hypot(1, 2").is_err());
+    }
+
+    #[test]
+    fn exposes_host_objects() {
+        let mut lox = quiet();
+        lox.define("open", |contents: String| Rc::new(File { contents, position: Cell::new(0) }));
+        lox.set_global("file", Value::Nil);
+
+        assert_eq!(lox.eval("file = open(\"hello\")").unwrap().to_string(), "File instance");
+        assert_eq!(lox.eval("file.position = 1 + 1").unwrap(), Value::Natural(2));
+        assert_eq!(lox.eval("file.read() + \"!\"").unwrap(), Value::String(Rc::from("llo!")));
+        assert_eq!(lox.eval("file.position == file.size").unwrap(), Value::Boolean(true));
+        assert_eq!(lox.eval("file == file").unwrap(), Value::Boolean(true));
+        assert_eq!(lox.eval("file == open(\"hello\")").unwrap(), Value::Boolean(false));
+
+        assert_eq!(runtime_error(&mut lox, "file.mode"), ("undefined property 'mode' on File".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "\nfile.size = 1"), ("can't set property 'size' on File".to_string(), 2));
+        assert_eq!(runtime_error(&mut lox, "file.position = true"), ("expected integer, got bool".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "file.position = -1"), ("position can't be negative".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "file.read(1)"), ("expected 0 arguments but got 1".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "file.write()"), ("undefined method 'write' on File".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "\"a\".size"), ("only objects have properties".to_string(), 1));
+        assert_eq!(runtime_error(&mut lox, "nil.read()"), ("only objects have methods".to_string(), 1));
+        assert!(lox.eval("file.size + 1 = 2").is_err());
+        assert!(lox.eval("file.").is_err());
+    }
+
+    #[test]
+    fn natives_get_host_objects_back() {
+        let mut lox = quiet();
+        lox.define("open", |contents: String| Rc::new(File { contents, position: Cell::new(0) }));
+        lox.define("remaining", |file: Rc<File>| (file.contents.len() - file.position.get()) as i64);
+        lox.define("either", |a: Option<Rc<File>>, b: Rc<dyn HostObject>| a.is_some() && b.type_name() == "File");
+
+        assert_eq!(lox.eval("remaining(open(\"hello\"))").unwrap(), Value::Natural(5));
+        assert_eq!(lox.eval("either(nil, open(\"\"))").unwrap(), Value::Boolean(false));
+        assert_eq!(runtime_error(&mut lox, "remaining(1)").0, "expected File, got number");
+        assert_eq!(Rc::<File>::from_lox(Value::Nil).err().unwrap().expected, "File");
+    }
+}
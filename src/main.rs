@@ -1,46 +1,487 @@
 extern crate rustilox;
-extern crate rprompt;
+extern crate rustyline;
+
+mod editor;
 
 use std::env;
 use std::error::Error;
+use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+use rustilox::{JsonTracer, Lox, LoxError, Scanner, TextTracer, TokenType, TraceFlags, Tracer, Value};
+use rustilox::{assembler, bytecode, json};
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+
+use editor::LoxHelper;
+
+/// Exit codes from BSD's sysexits.h, as used by clox
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
+
+const SUBCOMMANDS: [&str; 8] = ["repl", "run", "check", "disasm", "tokens", "compile", "assemble", "trace-diff"];
+
+/// The REPL's commands and what they do
+const COMMANDS: [(&str, &str); 8] = [
+    (":help", "show this message"),
+    (":load <file>", "run a file"),
+    (":reset", "start a fresh session"),
+    (":globals", "list the defined globals"),
+    (":dis <expr>", "show the bytecode an expression compiles to"),
+    (":tokens <src>", "show the tokens source scans to"),
+    (":time <expr>", "run an expression and show how long it took"),
+    (":quit", "leave the REPL")
+];
+
+fn interpret(source: &str, tracer: &mut Option<Box<dyn Tracer>>) -> Result<Value, LoxError> {
+    match tracer {
+        Some(tracer) => rustilox::interpret_traced(source, tracer.as_mut()),
+        None => rustilox::interpret(source)
+    }
+}
 
-use rustilox::Value;
+/// Evaluate input until it runs out, carrying on after errors. Input that
+/// stops part way through an expression is continued on the next line
+fn repl(tracer: Option<Box<dyn Tracer>>) -> Result<(), Box<dyn Error>> {
+    // one session for the whole REPL, so globals outlive each input
+    let mut lox = Lox::init();
+    define_natives(&mut lox);
+    if let Some(tracer) = tracer {
+        lox.set_tracer(tracer);
+    }
 
-fn repl() -> Result<Value, Box<Error>> {
+    let mut editor: Editor<LoxHelper, DefaultHistory> = Editor::new()?;
+    let commands = COMMANDS.iter().map(|&(usage, _)| usage.split(' ').next().unwrap()).collect();
+    editor.set_helper(Some(LoxHelper::init(commands)));
+    let history = history_path();
+    if let Some(ref path) = history {
+        // there's no history yet the first time round
+        let _ = editor.load_history(path);
+    }
+
+    let result = read_eval_print(&mut editor, &mut lox);
+    if let Some(ref path) = history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("can't save history: {}", e);
+        }
+    }
+    result
+}
+
+/// Define the globals every REPL session starts out with
+fn define_natives(lox: &mut Lox) {
+    let start = Instant::now();
+    lox.define("clock", move || start.elapsed().as_secs_f64());
+}
+
+/// `~/.rustilox_history`, if there's a home directory to put it in
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rustilox_history"))
+}
+
+fn read_eval_print(editor: &mut Editor<LoxHelper, DefaultHistory>, lox: &mut Lox) -> Result<(), Box<dyn Error>> {
+    let mut source = String::new();
     loop {
-        let line = rprompt::prompt_reply_stdout("> ")?;
-        if line.is_empty() {
-            return Ok(Value::Nil);
+        let prompt = if source.is_empty() { "> " } else { "... " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Eof) => return Ok(()),
+            // Ctrl-C abandons the input so far rather than the REPL
+            Err(ReadlineError::Interrupted) => {
+                source.clear();
+                continue;
+            },
+            Err(e) => return Err(Box::new(e))
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+        source.push_str(&line);
+        source.push('\n');
+
+        if source.trim().is_empty() {
+            source.clear();
+            continue;
+        }
+        if source.starts_with(':') {
+            let command = source.trim().to_string();
+            source.clear();
+            match meta_command(&command, lox) {
+                Ok(true) => (),
+                Ok(false) => return Ok(()),
+                Err(e) => eprintln!("{}", e)
+            }
+            update_globals(editor, lox);
+            continue;
+        }
+        if rustilox::is_incomplete(&source) {
+            continue;
+        }
+
+        if let Err(e) = lox.eval(&source) {
+            report(e);
+        }
+        update_globals(editor, lox);
+        source.clear();
+    }
+}
+
+/// Offer the session's current globals for completion
+fn update_globals(editor: &mut Editor<LoxHelper, DefaultHistory>, lox: &Lox) {
+    if let Some(helper) = editor.helper_mut() {
+        helper.set_globals(lox.globals().iter().map(|&(name, _)| name.to_string()).collect());
+    }
+}
+
+/// Run a script, or a file written by `compile`
+fn run_file(path: &str, tracer: &mut Option<Box<dyn Tracer>>) -> Result<(), Box<dyn Error>> {
+    let bytes = read_input(path)?;
+    if bytecode::is_compiled(&bytes) {
+        let chunk = bytecode::read(&bytes)?;
+        match tracer {
+            Some(tracer) => rustilox::run(&chunk, Some(tracer.as_mut()))?,
+            None => rustilox::run(&chunk, None)?
+        };
+    }
+    else {
+        interpret(&into_source(bytes)?, tracer)?;
+    }
+    Ok(())
+}
+
+/// Compile a script, reporting any errors but running nothing
+fn check(path: &str) -> Result<(), Box<dyn Error>> {
+    rustilox::compile(&read_file(path)?)?;
+    Ok(())
+}
+
+/// Print the bytecode a script, or compiled file, holds: clox style with a
+/// header before each function, or as JSON describing every instruction
+fn disasm(path: &str, as_json: bool) -> Result<(), Box<dyn Error>> {
+    let bytes = read_input(path)?;
+    let chunk = if bytecode::is_compiled(&bytes) {
+        bytecode::read(&bytes)?
+    }
+    else {
+        rustilox::compile(&into_source(bytes)?)?
+    };
+
+    // a script compiles to a single chunk until there are functions
+    let functions = vec![("script", &chunk)];
+    if as_json {
+        let functions = functions.iter().map(|&(name, chunk)| {
+            let constants = chunk.constants.values[..chunk.constants.count].iter()
+                .map(|value| json::string(&value.to_string()));
+            let mut instructions = Vec::new();
+            let mut offset = 0;
+            while offset < chunk.count {
+                instructions.push(chunk.describe_instruction(offset).to_string());
+                offset = chunk.next_instruction(offset);
+            }
+            json::Object::new()
+                .string("name", name)
+                .raw("constants", json::array(constants))
+                .raw("instructions", json::array(instructions))
+                .to_string()
+        });
+        println!("{}", json::array(functions));
+    }
+    else {
+        for (name, chunk) in functions {
+            println!("== {} ==", name);
+            print!("{}", chunk);
+        }
+    }
+    Ok(())
+}
+
+/// Compile a script to `out`, or by default to the script's path with a
+/// `.loxc` extension; a script read from stdin is compiled to stdout
+fn compile(path: &str, out: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let chunk = rustilox::compile(&read_file(path)?)?;
+    write_chunk(&chunk, path, out)
+}
+
+/// Assemble the instructions in `path` and write them out like `compile`
+fn assemble(path: &str, out: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let chunk = assembler::assemble(&read_file(path)?)?;
+    write_chunk(&chunk, path, out)
+}
+
+/// Write `chunk` to `out`, or next to `path` with a `.loxc` extension
+fn write_chunk(chunk: &rustilox::Chunk, path: &str, out: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let bytes = bytecode::write(chunk)?;
+    match out {
+        Some(out) => fs::write(out, bytes)?,
+        None if path == "-" => io::stdout().write_all(&bytes)?,
+        None => fs::write(Path::new(path).with_extension("loxc"), bytes)?
+    }
+    Ok(())
+}
+
+/// Run a REPL command such as `:load file.lox`, returning false once the
+/// REPL should exit
+fn meta_command(command: &str, lox: &mut Lox) -> Result<bool, Box<dyn Error>> {
+    let (name, argument) = match command.find(char::is_whitespace) {
+        Some(space) => (&command[..space], command[space..].trim()),
+        None => (command, "")
+    };
+
+    match (name, argument) {
+        (":help", _) => {
+            for &(usage, description) in COMMANDS.iter() {
+                println!("{:<16} {}", usage, description);
+            }
+        },
+        (":load", path) if !path.is_empty() => {
+            if let Err(e) = lox.eval(&read_file(path)?) {
+                report(e);
+            }
+        },
+        (":reset", "") => {
+            lox.reset();
+            define_natives(lox);
+            println!("session reset");
+        },
+        (":globals", "") => {
+            let globals = lox.globals();
+            if globals.is_empty() {
+                println!("no globals defined");
+            }
+            for (name, value) in globals {
+                println!("{} = {}", name, value);
+            }
+        },
+        (":dis", source) if !source.is_empty() => print!("{}", rustilox::compile(source)?),
+        (":tokens", source) if !source.is_empty() => print_tokens(source, false),
+        (":time", source) if !source.is_empty() => {
+            let start = Instant::now();
+            let result = lox.eval(source);
+            let elapsed = start.elapsed();
+            if let Err(e) = result {
+                report(e);
+            }
+            println!("took {:?}", elapsed);
+        },
+        (":quit", "") => return Ok(false),
+        _ => return Err(format!("unknown command '{}', try :help", command).into())
+    }
+    Ok(true)
+}
+
+/// Print an error from interpreting REPL input
+fn report(e: LoxError) {
+    match e {
+        // the VM has already reported it
+        LoxError::RuntimeError(_, _) => (),
+        e => eprintln!("{}", e)
+    }
+}
+
+/// Build the tracer asked for by `--trace=<kinds>`, `--trace-format=<format>`
+/// and `--trace-file=<path>`, writing to stderr unless a file was given
+fn tracer(kinds: Option<&str>, format: Option<&str>, path: Option<&str>) -> Result<Option<Box<dyn Tracer>>, Box<dyn Error>> {
+    let flags = match (kinds, format) {
+        (Some(kinds), _) => TraceFlags::parse(kinds)?,
+        // asking for a format alone means tracing execution
+        (None, Some(_)) => TraceFlags { scan: false, exec: true },
+        (None, None) => return Ok(None)
+    };
+    let out: Box<dyn Write> = match path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stderr())
+    };
+    match format {
+        None | Some("text") => Ok(Some(Box::new(TextTracer::init(out, flags)))),
+        Some("json") => Ok(Some(Box::new(JsonTracer::init(out, flags)))),
+        Some(format) => Err(format!("unknown trace format '{}', expected 'text' or 'json'", format).into())
+    }
+}
+
+/// Compare two JSON Lines traces, returning whether they match
+fn trace_diff(left: &str, right: &str) -> Result<bool, Box<dyn Error>> {
+    let divergence = match rustilox::trace_diff(&read_file(left)?, &read_file(right)?) {
+        Some(divergence) => divergence,
+        None => {
+            println!("traces match");
+            return Ok(true);
+        }
+    };
+
+    println!("traces diverge at step {}", divergence.step);
+    let end = String::from("<end of trace>");
+    println!("< {}", divergence.left.as_ref().unwrap_or(&end));
+    println!("> {}", divergence.right.as_ref().unwrap_or(&end));
+    Ok(false)
+}
+
+/// The contents of the file at `path`, or of stdin if `path` is `-`
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+    else {
+        fs::read(path)
+    }
+}
+
+fn into_source(bytes: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "source isn't valid utf-8"))
+}
+
+/// Source read from `path`, or from stdin if `path` is `-`
+fn read_file(path: &str) -> Result<String, Box<dyn Error>> {
+    Ok(into_source(read_input(path)?)?)
+}
+
+/// Print every token in a file with its position, as text or as JSON
+fn tokens(filename: &str, as_json: bool) -> Result<(), Box<dyn Error>> {
+    let source = read_file(filename)?;
+    print_tokens(&source, as_json);
+    Ok(())
+}
+
+fn print_tokens(source: &str, as_json: bool) {
+    let mut objects = Vec::new();
+    for token in Scanner::init(source) {
+        let lexeme = token.lexeme(source);
+        let column = token.column(source);
+        let end = token.start + token.length;
+
+        if as_json {
+            let mut object = json::Object::new()
+                .string("kind", token.token_type.name())
+                .string("lexeme", lexeme)
+                .number("line", token.line)
+                .number("column", column)
+                .raw("span", json::array(vec![token.start.to_string(), end.to_string()]));
+            if let TokenType::Error(ref msg, offset) = token.token_type {
+                object = object.string("message", msg).number("offset", offset);
+            }
+            objects.push(object.to_string());
         }
+        else {
+            let message = match token.token_type {
+                TokenType::Error(ref msg, _) => format!(" ({})", msg),
+                _ => String::new()
+            };
+            println!("{:>4}:{:<3} {:>5}..{:<5} {:<14} {}{}", token.line, column, token.start, end, token.token_type.name(), json::string(lexeme), message);
+        }
+    }
 
-        rustilox::interpret(&line)?;
+    if as_json && objects.is_empty() {
+        println!("[]");
+    }
+    else if as_json {
+        println!("[\n  {}\n]", objects.join(",\n  "));
     }
 }
 
-fn run_file(filename: &str) -> Result<Value, Box<Error>> {
-    let mut f = File::open(filename)?;
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
+fn usage() {
+    eprintln!("Usage: rustilox [options] [repl]            start a REPL");
+    eprintln!("       rustilox [options] [run] <path|->    run a script, or compiled file");
+    eprintln!("       rustilox [options] -e <code>         run code given inline");
+    eprintln!("       rustilox check <path|->              compile without running");
+    eprintln!("       rustilox disasm <path|-> [--json]    show the compiled bytecode");
+    eprintln!("       rustilox tokens <path|-> [--json]    show the scanned tokens");
+    eprintln!("       rustilox compile <path|-> [-o <out>] write the compiled bytecode to a file");
+    eprintln!("       rustilox assemble <path|-> [-o <out>] write hand-written bytecode to a file");
+    eprintln!("       rustilox trace-diff <left.jsonl> <right.jsonl>");
+    eprintln!();
+    eprintln!("Options: --trace=exec,scan --trace-format=text|json --trace-file=<path>");
+}
 
-    match rustilox::interpret(&contents) {
-        Ok(v) => Ok(v),
-        Err(e) => Err(Box::new(e))
+/// Report `e` on stderr, unless the VM already has, and pick the exit code
+/// clox's test runner expects for it
+fn failure(e: Box<dyn Error>) -> i32 {
+    if let Some(e) = e.downcast_ref::<LoxError>() {
+        return match *e {
+            LoxError::RuntimeError(_, _) => EX_SOFTWARE,
+            _ => {
+                eprintln!("{}", e);
+                EX_DATAERR
+            }
+        };
+    }
+
+    eprintln!("{}", e);
+    if e.is::<io::Error>() || e.is::<ReadlineError>() {
+        EX_IOERR
+    }
+    else {
+        EX_USAGE
     }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut trace_kinds = None;
+    let mut trace_file = None;
+    let mut trace_format = None;
+    let mut args: Vec<String> = Vec::new();
+    for arg in env::args().skip(1) {
+        if let Some(kinds) = arg.strip_prefix("--trace=") {
+            trace_kinds = Some(kinds.to_string());
+        }
+        else if let Some(path) = arg.strip_prefix("--trace-file=") {
+            trace_file = Some(path.to_string());
+        }
+        else if let Some(format) = arg.strip_prefix("--trace-format=") {
+            trace_format = Some(format.to_string());
+        }
+        else {
+            args.push(arg);
+        }
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
 
-    let result: Result<Value, Box<Error>> = match args.len() {
-        1 => repl(),
-        2 => run_file(&args[1]),
-        _ => {
-            println!("Usage: rustilox [path]");
-            return;
+    let mut tracer = match tracer(trace_kinds.as_deref(), trace_format.as_deref(), trace_file.as_deref()) {
+        Ok(tracer) => tracer,
+        Err(e) => exit(failure(e))
+    };
+
+    let result = match args[..] {
+        [] | ["repl"] => repl(tracer.take()),
+        ["-e", code] | ["run", "-e", code] => interpret(code, &mut tracer).map(|_| ()).map_err(Box::from),
+        ["run", path] => run_file(path, &mut tracer),
+        ["check", path] => check(path),
+        ["disasm", path] => disasm(path, false),
+        ["disasm", path, "--json"] => disasm(path, true),
+        ["tokens", path] => tokens(path, false),
+        ["tokens", path, "--json"] => tokens(path, true),
+        ["compile", path] => compile(path, None),
+        ["compile", path, "-o", out] => compile(path, Some(out)),
+        ["assemble", path] => assemble(path, None),
+        ["assemble", path, "-o", out] => assemble(path, Some(out)),
+        ["trace-diff", left, right] => match trace_diff(left, right) {
+            Ok(true) => Ok(()),
+            Ok(false) => exit(1),
+            Err(e) => Err(e)
         },
+        [path] if !SUBCOMMANDS.contains(&path) => run_file(path, &mut tracer),
+        _ => {
+            usage();
+            exit(EX_USAGE)
+        }
     };
 
-    println!("Result: {:?}", result);
+    match result {
+        Ok(()) => exit(0),
+        Err(e) => exit(failure(e))
+    }
+}
+
+/// Exit once everything printed so far has been written out
+fn exit(code: i32) -> ! {
+    let _ = io::stdout().flush();
+    process::exit(code)
 }
@@ -1,14 +1,169 @@
-pub const OP_RETURN:u8       =  0;
-pub const OP_CONSTANT:u8     =  1;
-pub const OP_NEGATE:u8       =  2;
-pub const OP_ADD:u8          =  3;
-pub const OP_SUBTRACT:u8     =  4;
-pub const OP_MULTIPLY:u8     =  5;
-pub const OP_DIVIDE:u8       =  6;
-pub const OP_NOT:u8          =  7;
-pub const OP_EQUAL:u8        =  8;
-pub const OP_NOTEQUAL:u8     =  9;
-pub const OP_GREATER:u8      = 10;
-pub const OP_GREATEREQUAL:u8 = 11;
-pub const OP_LESSER:u8       = 12;
-pub const OP_LESSEREQUAL:u8  = 13;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Describes the bytes that follow an opcode in a chunk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operands {
+    /// the opcode stands alone
+    None,
+    /// a single byte indexing into the chunk's constant table
+    Constant,
+    /// a big-endian u16 distance to skip forward, measured from the end of
+    /// the instruction
+    Jump,
+    /// a single byte counting the arguments of a call
+    Count,
+    /// a single byte indexing a string constant that names a global or a
+    /// property
+    Name,
+    /// a `Name` byte for the method, then a `Count` byte for its arguments
+    Invoke,
+}
+
+impl Operands {
+    /// The number of bytes the operands occupy after the opcode itself
+    pub fn width(self) -> usize {
+        match self {
+            Operands::None => 0,
+            Operands::Constant => 1,
+            Operands::Jump => 2,
+            Operands::Count => 1,
+            Operands::Name => 1,
+            Operands::Invoke => 2,
+        }
+    }
+}
+
+/// Everything the tooling needs to know about an opcode without executing it
+#[derive(Debug)]
+pub struct OpInfo {
+    pub mnemonic: &'static str,
+    pub operands: Operands,
+    /// how many values the instruction pops off of the stack, plus however
+    /// many arguments its `Count` or `Invoke` operands say
+    pub pops: usize,
+    /// how many values the instruction pushes onto the stack
+    pub pushes: usize,
+}
+
+impl OpInfo {
+    /// The total number of bytes the instruction occupies, including the opcode
+    pub fn size(&self) -> usize {
+        1 + self.operands.width()
+    }
+}
+
+// every opcode is declared exactly once here; the enum, the byte decoding,
+// and the metadata table are all generated from this list
+macro_rules! opcodes {
+    ($($name:ident = $byte:expr => $mnemonic:expr, $operands:ident, $pops:expr, $pushes:expr;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum OpCode {
+            $($name = $byte,)*
+        }
+
+        pub static OPCODES: &[OpInfo] = &[
+            $(OpInfo {
+                mnemonic: $mnemonic,
+                operands: Operands::$operands,
+                pops: $pops,
+                pushes: $pushes,
+            },)*
+        ];
+
+        impl TryFrom<u8> for OpCode {
+            type Error = u8;
+
+            fn try_from(byte: u8) -> Result<OpCode, u8> {
+                match byte {
+                    $($byte => Ok(OpCode::$name),)*
+                    _ => Err(byte)
+                }
+            }
+        }
+    };
+}
+
+opcodes! {
+    Return       =  0 => "OP_RETURN",        None,     1, 0;
+    Constant     =  1 => "OP_CONSTANT",      Constant, 0, 1;
+    Negate       =  2 => "OP_NEGATE",        None,     1, 1;
+    Add          =  3 => "OP_ADD",           None,     2, 1;
+    Subtract     =  4 => "OP_SUBTRACT",      None,     2, 1;
+    Multiply     =  5 => "OP_MULTIPLY",      None,     2, 1;
+    Divide       =  6 => "OP_DIVIDE",        None,     2, 1;
+    Not          =  7 => "OP_NOT",           None,     1, 1;
+    Equal        =  8 => "OP_EQUAL",         None,     2, 1;
+    NotEqual     =  9 => "OP_NOTEQUAL",      None,     2, 1;
+    Greater      = 10 => "OP_GREATER",       None,     2, 1;
+    GreaterEqual = 11 => "OP_GREATEREQUAL",  None,     2, 1;
+    Lesser       = 12 => "OP_LESSER",        None,     2, 1;
+    LesserEqual  = 13 => "OP_LESSEREQUAL",   None,     2, 1;
+    Nil          = 14 => "OP_NIL",           None,     0, 1;
+    True         = 15 => "OP_TRUE",          None,     0, 1;
+    False        = 16 => "OP_FALSE",         None,     0, 1;
+    Pop          = 17 => "OP_POP",           None,     1, 0;
+    Jump         = 18 => "OP_JUMP",          Jump,     0, 0;
+    JumpIfFalse  = 19 => "OP_JUMP_IF_FALSE", Jump,     1, 1;
+    ToString     = 20 => "OP_TO_STRING",     None,     1, 1;
+    GetGlobal    = 21 => "OP_GET_GLOBAL",    Name,     0, 1;
+    SetGlobal    = 22 => "OP_SET_GLOBAL",    Name,     1, 1;
+    Call         = 23 => "OP_CALL",          Count,    1, 1;
+    GetProperty  = 24 => "OP_GET_PROPERTY",  Name,     1, 1;
+    SetProperty  = 25 => "OP_SET_PROPERTY",  Name,     2, 1;
+    Invoke       = 26 => "OP_INVOKE",        Invoke,   1, 1;
+}
+
+impl OpCode {
+    /// Look up the metadata for this opcode
+    pub fn info(self) -> &'static OpInfo {
+        &OPCODES[self as usize]
+    }
+
+    /// Whether execution can continue on to the next instruction after this one
+    pub fn falls_through(self) -> bool {
+        !matches!(self, OpCode::Return | OpCode::Jump)
+    }
+
+    /// Find the opcode with the given mnemonic, i.e. `"OP_ADD"`
+    pub fn from_mnemonic(mnemonic: &str) -> Option<OpCode> {
+        OPCODES.iter()
+            .position(|info| info.mnemonic == mnemonic)
+            .and_then(|i| OpCode::try_from(i as u8).ok())
+    }
+}
+
+impl From<OpCode> for u8 {
+    fn from(op: OpCode) -> u8 {
+        op as u8
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.info().mnemonic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_matches_discriminants() {
+        for (i, info) in OPCODES.iter().enumerate() {
+            let op = OpCode::try_from(i as u8).expect("every table entry decodes");
+            assert_eq!(op as usize, i);
+            assert_eq!(op.info().mnemonic, info.mnemonic);
+        }
+        assert!(OpCode::try_from(OPCODES.len() as u8).is_err());
+    }
+
+    #[test]
+    fn mnemonic_lookup() {
+        assert_eq!(OpCode::from_mnemonic("OP_CONSTANT"), Some(OpCode::Constant));
+        assert_eq!(OpCode::from_mnemonic("OP_LESSEREQUAL"), Some(OpCode::LesserEqual));
+        assert_eq!(OpCode::from_mnemonic("OP_NOPE"), None);
+    }
+}
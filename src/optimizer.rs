@@ -0,0 +1,412 @@
+use std::convert::TryFrom;
+
+use chunk::{Chunk, MAX_CONSTANTS};
+use opcodes::{OpCode, Operands};
+use values::ValueArray;
+
+/// One instruction pulled out of a chunk so that it can be rewritten without
+/// worrying about byte offsets until the chunk is reassembled
+struct Decoded {
+    op: OpCode,
+    /// the constant index for instructions with a constant or name, the
+    /// argument count for calls, or the index of the target instruction for
+    /// jumps
+    operand: usize,
+    /// the argument count of an invoke
+    count: usize,
+    line: usize,
+    removed: bool
+}
+
+/// Rewrite common instruction sequences in a chunk into cheaper ones,
+/// keeping jump offsets and the line table consistent with the new code.
+/// Chunks that can't be decoded are handed back untouched.
+pub fn optimize(chunk: Chunk) -> Chunk {
+    let mut code = match decode(&chunk) {
+        Some(code) => code,
+        None => return chunk
+    };
+    let mut constants = chunk.constants;
+
+    loop {
+        let mut changed = false;
+        changed |= thread_jumps(&mut code);
+        changed |= remove_jumps_to_next(&mut code);
+        changed |= fuse_not(&mut code);
+        changed |= fold_negated_constants(&mut code, &mut constants);
+        changed |= remove_dead_code(&mut code);
+        if !changed {
+            break;
+        }
+    }
+
+    encode(&code, constants)
+}
+
+fn decode(chunk: &Chunk) -> Option<Vec<Decoded>> {
+    let mut code: Vec<Decoded> = Vec::new();
+    let mut indices: Vec<Option<usize>> = vec![None; chunk.count + 1];
+
+    let mut offset: usize = 0;
+    while offset < chunk.count {
+        let op = OpCode::try_from(chunk.code[offset]).ok()?;
+        let info = op.info();
+        if offset + info.size() > chunk.count {
+            return None;
+        }
+
+        let operand = match info.operands {
+            Operands::None => 0,
+            Operands::Constant | Operands::Count | Operands::Name | Operands::Invoke => chunk.code[offset + 1] as usize,
+            Operands::Jump => chunk.jump_target(offset)
+        };
+        let count = match info.operands {
+            Operands::Invoke => chunk.code[offset + 2] as usize,
+            _ => 0
+        };
+        indices[offset] = Some(code.len());
+        code.push(Decoded { op, operand, count, line: chunk.lines[offset], removed: false });
+        offset += info.size();
+    }
+    indices[chunk.count] = Some(code.len());
+
+    // turn jump targets from byte offsets into instruction indices
+    for instruction in code.iter_mut() {
+        if instruction.op.info().operands == Operands::Jump {
+            instruction.operand = (*indices.get(instruction.operand)?)?;
+        }
+    }
+
+    Some(code)
+}
+
+fn encode(code: &[Decoded], constants: ValueArray) -> Chunk {
+    // drop constants nothing refers to any more
+    let mut used: Vec<bool> = vec![false; constants.count];
+    for instruction in code.iter().filter(|i| !i.removed && uses_constant(i.op.info().operands)) {
+        used[instruction.operand] = true;
+    }
+    let mut remap: Vec<usize> = vec![0; used.len()];
+    let mut kept = Vec::new();
+    for (i, value) in constants.values.into_iter().take(used.len()).enumerate() {
+        if used[i] {
+            remap[i] = kept.len();
+            kept.push(value);
+        }
+    }
+
+    // work out where every instruction will land
+    let mut offsets: Vec<usize> = Vec::with_capacity(code.len() + 1);
+    let mut offset: usize = 0;
+    for instruction in code {
+        offsets.push(offset);
+        if !instruction.removed {
+            offset += instruction.op.info().size();
+        }
+    }
+    offsets.push(offset);
+
+    let mut bytes: Vec<u8> = Vec::with_capacity(offset);
+    let mut lines: Vec<usize> = Vec::with_capacity(offset);
+    for (i, instruction) in code.iter().enumerate().filter(|(_, i)| !i.removed) {
+        let mut emit = |byte: u8| {
+            bytes.push(byte);
+            lines.push(instruction.line);
+        };
+
+        emit(instruction.op as u8);
+        match instruction.op.info().operands {
+            Operands::None => (),
+            Operands::Constant | Operands::Name => emit(remap[instruction.operand] as u8),
+            Operands::Invoke => {
+                emit(remap[instruction.operand] as u8);
+                emit(instruction.count as u8);
+            },
+            Operands::Count => emit(instruction.operand as u8),
+            Operands::Jump => {
+                let target = offsets[resolve(code, instruction.operand)];
+                let jump = target - (offsets[i] + 3);
+                emit(((jump >> 8) & 0xff) as u8);
+                emit((jump & 0xff) as u8);
+            }
+        }
+    }
+
+    Chunk::build(ValueArray::build(kept), bytes, lines)
+}
+
+/// Whether an instruction's operand indexes the constant table
+fn uses_constant(operands: Operands) -> bool {
+    matches!(operands, Operands::Constant | Operands::Name | Operands::Invoke)
+}
+
+/// The first instruction at or after `index` that is still part of the code
+fn resolve(code: &[Decoded], index: usize) -> usize {
+    let mut index = index;
+    while index < code.len() && code[index].removed {
+        index += 1;
+    }
+    index
+}
+
+fn next_live(code: &[Decoded], index: usize) -> usize {
+    resolve(code, index + 1)
+}
+
+/// Which instructions some jump lands on. Passes only ever remove
+/// instructions that aren't targets, so this stays accurate for a whole pass.
+fn jump_targets(code: &[Decoded]) -> Vec<bool> {
+    let mut targets: Vec<bool> = vec![false; code.len() + 1];
+    for instruction in code.iter().filter(|i| !i.removed && i.op.info().operands == Operands::Jump) {
+        targets[resolve(code, instruction.operand)] = true;
+    }
+    targets
+}
+
+/// A jump that lands on an unconditional jump can go straight to the final
+/// destination. Since `OP_JUMP_IF_FALSE` leaves its condition on the stack, a
+/// conditional jump landing on another conditional jump will take that one
+/// too, so it can skip ahead as well.
+fn thread_jumps(code: &mut [Decoded]) -> bool {
+    let mut changed = false;
+    for i in 0..code.len() {
+        if code[i].removed || code[i].op.info().operands != Operands::Jump {
+            continue;
+        }
+
+        let mut target = resolve(code, code[i].operand);
+        let mut hops = 0;
+        while target < code.len() && hops < code.len() {
+            let next = &code[target];
+            let follow = next.op == OpCode::Jump
+                || (code[i].op == OpCode::JumpIfFalse && next.op == OpCode::JumpIfFalse);
+            if !follow || target == i {
+                break;
+            }
+            target = resolve(code, next.operand);
+            hops += 1;
+        }
+
+        if target != resolve(code, code[i].operand) {
+            code[i].operand = target;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Jumps to the very next instruction do nothing at all
+fn remove_jumps_to_next(code: &mut [Decoded]) -> bool {
+    let mut changed = false;
+    for i in 0..code.len() {
+        if !code[i].removed
+            && code[i].op.info().operands == Operands::Jump
+            && resolve(code, code[i].operand) == next_live(code, i) {
+            code[i].removed = true;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// `OP_NOT` straight after a comparison becomes the inverse comparison
+fn fuse_not(code: &mut [Decoded]) -> bool {
+    let targets = jump_targets(code);
+    let mut changed = false;
+    for i in 0..code.len() {
+        if code[i].removed {
+            continue;
+        }
+
+        let inverse = match code[i].op {
+            OpCode::Equal => OpCode::NotEqual,
+            OpCode::NotEqual => OpCode::Equal,
+            OpCode::Greater => OpCode::LesserEqual,
+            OpCode::GreaterEqual => OpCode::Lesser,
+            OpCode::Lesser => OpCode::GreaterEqual,
+            OpCode::LesserEqual => OpCode::Greater,
+            _ => continue
+        };
+
+        let next = next_live(code, i);
+        if next < code.len() && code[next].op == OpCode::Not && !targets[next] {
+            code[i].op = inverse;
+            code[next].removed = true;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// `OP_CONSTANT` followed by `OP_NEGATE` loads the negated constant instead
+fn fold_negated_constants(code: &mut [Decoded], constants: &mut ValueArray) -> bool {
+    let targets = jump_targets(code);
+    let mut changed = false;
+    for i in 0..code.len() {
+        if code[i].removed || code[i].op != OpCode::Constant {
+            continue;
+        }
+
+        let next = next_live(code, i);
+        if next >= code.len() || code[next].op != OpCode::Negate || targets[next] {
+            continue;
+        }
+        if constants.count == MAX_CONSTANTS {
+            continue;
+        }
+
+        if let Ok(negated) = -constants.values[code[i].operand].clone() {
+            constants.write(negated);
+            code[i].operand = constants.count - 1;
+            code[next].removed = true;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Anything that can't be reached from the start of the chunk is dropped
+fn remove_dead_code(code: &mut [Decoded]) -> bool {
+    let mut reachable: Vec<bool> = vec![false; code.len()];
+    let mut pending: Vec<usize> = vec![resolve(code, 0)];
+    while let Some(i) = pending.pop() {
+        if i >= code.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+
+        if code[i].op.info().operands == Operands::Jump {
+            pending.push(resolve(code, code[i].operand));
+        }
+        if code[i].op.falls_through() {
+            pending.push(next_live(code, i));
+        }
+    }
+
+    let mut changed = false;
+    for (instruction, reachable) in code.iter_mut().zip(reachable) {
+        if !instruction.removed && !reachable {
+            instruction.removed = true;
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::{compile_with, Options};
+    use values::Value;
+
+    const RAW: Options = Options { fold_constants: false, optimize: false };
+
+    fn golden(chunk: Chunk, before: &str, after: &str) {
+        assert_eq!(format!("{}", chunk), before);
+        let optimized = optimize(chunk);
+        assert_eq!(format!("{}", optimized), after);
+        assert!(::verifier::verify(&optimized).is_ok());
+    }
+
+    #[test]
+    fn not_after_comparison() {
+        golden(compile_with("!(1 < 2)", RAW).unwrap(),
+"0000 0001      OP_CONSTANT 0000 '1'
+0002    |      OP_CONSTANT 0001 '2'
+0004    |        OP_LESSER
+0005    |           OP_NOT
+0006    |        OP_RETURN
+",
+"0000 0001      OP_CONSTANT 0000 '1'
+0002    |      OP_CONSTANT 0001 '2'
+0004    |  OP_GREATEREQUAL
+0005    |        OP_RETURN
+");
+    }
+
+    #[test]
+    fn negated_constant() {
+        golden(compile_with("-3 * -4", RAW).unwrap(),
+"0000 0001      OP_CONSTANT 0000 '3'
+0002    |        OP_NEGATE
+0003    |      OP_CONSTANT 0001 '4'
+0005    |        OP_NEGATE
+0006    |      OP_MULTIPLY
+0007    |        OP_RETURN
+",
+"0000 0001      OP_CONSTANT 0000 '-3'
+0002    |      OP_CONSTANT 0001 '-4'
+0004    |      OP_MULTIPLY
+0005    |        OP_RETURN
+");
+    }
+
+    #[test]
+    fn conditional_jump_threading() {
+        golden(compile_with("(nil and true) and false", RAW).unwrap(),
+"0000 0001           OP_NIL
+0001    | OP_JUMP_IF_FALSE 0001 -> 0006
+0004    |           OP_POP
+0005    |          OP_TRUE
+0006    | OP_JUMP_IF_FALSE 0006 -> 0011
+0009    |           OP_POP
+0010    |         OP_FALSE
+0011    |        OP_RETURN
+",
+"0000 0001           OP_NIL
+0001    | OP_JUMP_IF_FALSE 0001 -> 0011
+0004    |           OP_POP
+0005    |          OP_TRUE
+0006    | OP_JUMP_IF_FALSE 0006 -> 0011
+0009    |           OP_POP
+0010    |         OP_FALSE
+0011    |        OP_RETURN
+");
+    }
+
+    #[test]
+    fn jump_to_jump_and_dead_code() {
+        // jump over a jump that skips straight to the return, with some
+        // unreachable code stranded in between
+        let chunk = Chunk::build(
+            ValueArray::build(vec![Value::Real(1.0), Value::Real(2.0)]),
+            vec![
+                OpCode::True as u8,
+                OpCode::Jump as u8, 0, 2,
+                OpCode::Constant as u8, 0,
+                OpCode::Jump as u8, 0, 3,
+                OpCode::Constant as u8, 1,
+                OpCode::Pop as u8,
+                OpCode::Return as u8,
+                OpCode::Nil as u8,
+            ],
+            vec![1, 1, 1, 1, 2, 2, 2, 2, 2, 3, 3, 3, 4, 5]
+        );
+        golden(chunk,
+"0000 0001          OP_TRUE
+0001    |          OP_JUMP 0001 -> 0006
+0004 0002      OP_CONSTANT 0000 '1.0'
+0006    |          OP_JUMP 0006 -> 0012
+0009 0003      OP_CONSTANT 0001 '2.0'
+0011    |           OP_POP
+0012 0004        OP_RETURN
+0013 0005           OP_NIL
+",
+"0000 0001          OP_TRUE
+0001 0004        OP_RETURN
+");
+    }
+
+    #[test]
+    fn optimized_code_behaves_the_same() {
+        for src in &["!(1 < 2)", "-3 * -4", "nil and true and false", "false or !(2 >= -1) or 4", "!(1 == 1) and -2"] {
+            let raw = compile_with(src, RAW).unwrap();
+            let optimized = optimize(compile_with(src, RAW).unwrap());
+            assert!(optimized.count <= raw.count);
+
+            let expected = ::vm::VM::init(&raw).evaluate().unwrap();
+            let actual = ::vm::VM::init(&optimized).evaluate().unwrap();
+            assert_eq!(expected, actual, "{}", src);
+        }
+    }
+}
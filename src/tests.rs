@@ -1,30 +1,37 @@
-use super::opcodes::*;
+use super::opcodes::OpCode;
 use super::chunk::Chunk;
-use super::vm::VM;  
+use super::vm::VM;
 use super::values::Value;
 use super::values::ValueArray;
+use super::compiler::token::Token;
+use super::interpreter::{interpret_traced, interpret_with};
+use super::trace::{Tracer, JsonTracer, TraceFlags, diff};
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::io;
+use std::rc::Rc;
 
 #[test]
 fn test_instruction_at_a_time() {
     let mut chunk:Chunk = Chunk::init();
     let a = chunk.add_constant(Value::Real(1.2));
-    chunk.write(OP_CONSTANT, 123);
+    chunk.write(OpCode::Constant, 123);
     chunk.write(a, 123);
     let b = chunk.add_constant(Value::Real(3.4));
-    chunk.write(OP_CONSTANT, 123);
+    chunk.write(OpCode::Constant, 123);
     chunk.write(b, 123);
-    chunk.write(OP_ADD, 123);
+    chunk.write(OpCode::Add, 123);
     let c = chunk.add_constant(Value::Real(5.6));
-    chunk.write(OP_CONSTANT, 123);
+    chunk.write(OpCode::Constant, 123);
     chunk.write(c, 123);
-    chunk.write(OP_DIVIDE, 123);
-    chunk.write(OP_NEGATE, 123);
+    chunk.write(OpCode::Divide, 123);
+    chunk.write(OpCode::Negate, 123);
     let d = chunk.add_constant(Value::Real(0.0));
-    chunk.write(OP_CONSTANT, 124);
+    chunk.write(OpCode::Constant, 124);
     chunk.write(d, 124);
-    chunk.write(OP_LESSER, 124);
-    chunk.write(OP_NOT, 124);
-    chunk.write(OP_RETURN, 124);
+    chunk.write(OpCode::Lesser, 124);
+    chunk.write(OpCode::Not, 124);
+    chunk.write(OpCode::Return, 124);
 
     let mut vm: VM = VM::init(&chunk);
     let result = vm.evaluate().expect("evaluate");
@@ -35,11 +42,150 @@ fn test_instruction_at_a_time() {
 fn test_instruction_at_a_block() {
     let chunk:Chunk = Chunk::build(
         ValueArray::build(vec![Value::Real(1.2), Value::Real(3.4), Value::Real(5.6), Value::Real(0.0)]),
-        vec![OP_CONSTANT, 0, OP_CONSTANT, 1, OP_ADD, OP_CONSTANT, 2, OP_DIVIDE, OP_NEGATE, OP_CONSTANT, 3, OP_LESSER, OP_NOT, OP_RETURN],
+        vec![OpCode::Constant as u8, 0, OpCode::Constant as u8, 1, OpCode::Add as u8, OpCode::Constant as u8, 2, OpCode::Divide as u8, OpCode::Negate as u8, OpCode::Constant as u8, 3, OpCode::Lesser as u8, OpCode::Not as u8, OpCode::Return as u8],
         vec![123,123,123,123,123,123,123,123,124,124,124,124,125,125]
     );
 
     let mut vm: VM = VM::init(&chunk);
     let result = vm.evaluate().expect("evaluate");
     assert_eq!(result, Value::Boolean(false));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_stack_overflow_is_an_error() {
+    let mut code = vec![OpCode::Nil as u8; 300];
+    code.push(OpCode::Return as u8);
+    let lines = vec![1; code.len()];
+    let chunk:Chunk = Chunk::build(ValueArray::init(), code, lines);
+
+    let mut vm: VM = VM::init(&chunk);
+    assert!(vm.evaluate().is_err());
+}
+
+#[test]
+fn describes_instructions_structurally() {
+    let mut chunk = Chunk::init();
+    let a = chunk.add_constant(Value::String(Rc::from("a")));
+    chunk.write(OpCode::Constant, 1);
+    chunk.write(a, 1);
+    chunk.write(OpCode::JumpIfFalse, 2);
+    chunk.write(0, 2);
+    chunk.write(1, 2);
+    chunk.write(OpCode::Nil, 2);
+    chunk.write(OpCode::Return, 2);
+
+    let mut offsets = vec![0];
+    while *offsets.last().unwrap() < chunk.count {
+        let next = chunk.next_instruction(*offsets.last().unwrap());
+        offsets.push(next);
+    }
+    assert_eq!(offsets, vec![0, 2, 5, 6, 7]);
+
+    assert_eq!(chunk.describe_instruction(0).to_string(),
+               r#"{"offset":0,"line":1,"op":"OP_CONSTANT","operands":[0],"constant":"a"}"#);
+    assert_eq!(chunk.describe_instruction(2).to_string(),
+               r#"{"offset":2,"line":2,"op":"OP_JUMP_IF_FALSE","operands":[1],"target":6}"#);
+    assert_eq!(chunk.describe_instruction(6).to_string(),
+               r#"{"offset":6,"line":2,"op":"OP_RETURN","operands":[]}"#);
+}
+
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>
+}
+
+impl Tracer for Recorder {
+    fn on_token(&mut self, token: &Token) {
+        self.events.push(format!("token {}", token.token_type.name()));
+    }
+
+    fn before_instruction(&mut self, chunk: &Chunk, offset: usize, stack: &[Value]) {
+        let op = OpCode::try_from(chunk.code[offset]).unwrap();
+        let stack: Vec<String> = stack.iter().map(|v| v.to_string()).collect();
+        self.events.push(format!("{} [{}]", op, stack.join(", ")));
+    }
+
+    fn on_call(&mut self, name: &str, depth: usize) {
+        self.events.push(format!("call {} {}", name, depth));
+    }
+
+    fn on_return(&mut self, value: &Value) {
+        self.events.push(format!("return {}", value));
+    }
+}
+
+#[test]
+fn tracer_sees_tokens_and_instructions() {
+    let mut recorder = Recorder::default();
+    let result = interpret_traced("1 + 2 /* three */", &mut recorder).unwrap();
+    assert_eq!(result, Value::Natural(3));
+    assert_eq!(recorder.events, vec![
+        "token NUMBER", "token PLUS", "token NUMBER", "token BLOCK_COMMENT", "token EOF",
+        "call script 0",
+        "OP_CONSTANT []",
+        "OP_RETURN [3]",
+        "return 3",
+    ]);
+}
+
+/// A writer whose contents can still be read after it's been boxed up
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn json_trace(source: &str) -> String {
+    let buffer = SharedBuffer::default();
+    let mut tracer = JsonTracer::init(Box::new(buffer.clone()), TraceFlags { scan: false, exec: true });
+    interpret_traced(source, &mut tracer).unwrap();
+    let trace = buffer.0.borrow();
+    String::from_utf8(trace.clone()).unwrap()
+}
+
+#[test]
+fn json_tracer_writes_one_line_per_instruction() {
+    assert_eq!(json_trace("\"a\" + \"b\""), concat!(
+        r#"{"offset":0,"line":1,"op":"OP_CONSTANT","operands":[0],"constant":"ab","stack":[]}"#, "\n",
+        r#"{"offset":2,"line":1,"op":"OP_RETURN","operands":[],"stack":["ab"]}"#, "\n",
+    ));
+
+    assert_eq!(diff(&json_trace("1 + 2"), &json_trace("3")), None);
+    // the first step already loads a different constant
+    assert_eq!(diff(&json_trace("1 + 2"), &json_trace("1 + 3")).unwrap().step, 1);
+}
+
+#[test]
+fn json_tracer_writes_tokens_when_asked() {
+    let buffer = SharedBuffer::default();
+    let mut tracer = JsonTracer::init(Box::new(buffer.clone()), TraceFlags { scan: true, exec: false });
+    interpret_traced("nil", &mut tracer).unwrap();
+    assert_eq!(String::from_utf8(buffer.0.borrow().clone()).unwrap(), concat!(
+        r#"{"line":1,"token":"nil"}"#, "\n",
+        r#"{"line":1,"token":"eof"}"#, "\n",
+    ));
+}
+
+#[test]
+fn output_and_diagnostics_go_to_their_sinks() {
+    let output = SharedBuffer::default();
+    let diagnostics = SharedBuffer::default();
+    let result = interpret_with("\"a\" + \"b\"", Box::new(output.clone()), Box::new(diagnostics.clone()));
+    assert_eq!(result.unwrap(), Value::String(Rc::from("ab")));
+    assert_eq!(*output.0.borrow(), b"ab\n");
+    assert!(diagnostics.0.borrow().is_empty());
+
+    let output = SharedBuffer::default();
+    let result = interpret_with("-true\n", Box::new(output.clone()), Box::new(diagnostics.clone()));
+    assert!(result.is_err());
+    assert!(output.0.borrow().is_empty());
+    assert_eq!(String::from_utf8(diagnostics.0.borrow().clone()).unwrap(),
+               "can't negate a non-numeric value\n[line 1] in script\n");
+}
@@ -0,0 +1,233 @@
+//! Hooks for watching the compiler and VM at work
+
+use std::io::Write;
+
+use chunk::Chunk;
+use compiler::token::Token;
+use json;
+use values::Value;
+
+/// Receives events from the compiler and VM as they happen. Every method
+/// does nothing by default, so implementations only override what they need
+pub trait Tracer {
+    /// The scanner produced a token
+    fn on_token(&mut self, _token: &Token) {}
+
+    /// The VM is about to execute the instruction at `offset`; `stack` holds
+    /// the values on the stack, bottom first
+    fn before_instruction(&mut self, _chunk: &Chunk, _offset: usize, _stack: &[Value]) {}
+
+    /// A function is being entered; `depth` is how many calls were already
+    /// active. The top-level script counts as a call named `script`
+    fn on_call(&mut self, _name: &str, _depth: usize) {}
+
+    /// A function returned `value`
+    fn on_return(&mut self, _value: &Value) {}
+}
+
+/// Which kinds of events to trace
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TraceFlags {
+    pub scan: bool,
+    pub exec: bool
+}
+
+impl TraceFlags {
+    /// Parse a comma separated list such as `exec,scan`
+    pub fn parse(list: &str) -> Result<TraceFlags, String> {
+        let mut flags = TraceFlags::default();
+        for name in list.split(',').filter(|name| !name.is_empty()) {
+            match name {
+                "scan" => flags.scan = true,
+                "exec" => flags.exec = true,
+                _ => return Err(format!("unknown trace kind '{}', expected 'scan' or 'exec'", name))
+            }
+        }
+        Ok(flags)
+    }
+
+    /// The flags selected by the `trace_scanner` and `trace_execution` features
+    pub fn from_features() -> TraceFlags {
+        TraceFlags {
+            scan: cfg!(feature = "trace_scanner"),
+            exec: cfg!(feature = "trace_execution")
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.scan || self.exec
+    }
+}
+
+/// Writes a human readable trace: tokens as they're scanned, and the stack
+/// followed by the disassembled instruction before each one is executed
+pub struct TextTracer {
+    out: Box<dyn Write>,
+    flags: TraceFlags,
+    line: usize
+}
+
+impl TextTracer {
+    pub fn init(out: Box<dyn Write>, flags: TraceFlags) -> TextTracer {
+        TextTracer {
+            out,
+            flags,
+            line: 0
+        }
+    }
+}
+
+// tracing is best effort, so write errors are ignored rather than being
+// allowed to abort the program being traced
+impl Tracer for TextTracer {
+    fn on_token(&mut self, token: &Token) {
+        if !self.flags.scan {
+            return;
+        }
+
+        if token.line != self.line {
+            let _ = write!(self.out, "{:04} ", token.line);
+            self.line = token.line;
+        }
+        else {
+            let _ = write!(self.out, "   | ");
+        }
+        let _ = writeln!(self.out, "{}", token.token_type);
+    }
+
+    fn before_instruction(&mut self, chunk: &Chunk, offset: usize, stack: &[Value]) {
+        if !self.flags.exec {
+            return;
+        }
+
+        let _ = write!(self.out, "          ");
+        for value in stack {
+            let _ = write!(self.out, "[ {} ]", value);
+        }
+        let _ = writeln!(self.out);
+        let _ = write!(self.out, "{}", chunk.get_instruction(offset));
+    }
+
+    fn on_call(&mut self, name: &str, depth: usize) {
+        if self.flags.exec {
+            let _ = writeln!(self.out, "{:width$}-> {}", "", name, width = depth * 2);
+        }
+    }
+
+    fn on_return(&mut self, value: &Value) {
+        if self.flags.exec {
+            let _ = writeln!(self.out, "<- {}", value);
+        }
+    }
+}
+
+/// Writes one JSON object per scanned token or executed instruction, one
+/// per line, for comparing runs with `diff`
+pub struct JsonTracer {
+    out: Box<dyn Write>,
+    flags: TraceFlags
+}
+
+impl JsonTracer {
+    pub fn init(out: Box<dyn Write>, flags: TraceFlags) -> JsonTracer {
+        JsonTracer {
+            out,
+            flags
+        }
+    }
+}
+
+impl Tracer for JsonTracer {
+    fn on_token(&mut self, token: &Token) {
+        if self.flags.scan {
+            let object = json::Object::new()
+                .number("line", token.line)
+                .string("token", &token.token_type.to_string());
+            let _ = writeln!(self.out, "{}", object);
+        }
+    }
+
+    fn before_instruction(&mut self, chunk: &Chunk, offset: usize, stack: &[Value]) {
+        if !self.flags.exec {
+            return;
+        }
+
+        let object = chunk.describe_instruction(offset)
+            .raw("stack", json::array(stack.iter().map(|v| json::string(&v.to_string()))));
+        let _ = writeln!(self.out, "{}", object);
+    }
+}
+
+/// Where two JSON Lines traces stop agreeing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// 1-based number of the first step that differs
+    pub step: usize,
+    /// that step in each trace, or `None` if the trace had already ended
+    pub left: Option<String>,
+    pub right: Option<String>
+}
+
+/// Step through two traces written by `JsonTracer` side by side, returning
+/// the first step whose token, opcode, line, constant, argument count or
+/// stack differ. Offsets, constant indexes and jump distances are ignored
+/// since they legitimately change when code is laid out differently, e.g.
+/// by the optimizer
+pub fn diff(left: &str, right: &str) -> Option<Divergence> {
+    let key = |step: &str| ["token", "op", "line", "constant", "count", "stack"].iter()
+        .map(|name| json::field(step, name).map(str::to_string))
+        .collect::<Vec<_>>();
+
+    let mut left = left.lines().filter(|l| !l.trim().is_empty());
+    let mut right = right.lines().filter(|l| !l.trim().is_empty());
+    let mut step = 0;
+    loop {
+        step += 1;
+        match (left.next(), right.next()) {
+            (None, None) => return None,
+            (Some(l), Some(r)) if key(l) == key(r) => continue,
+            (l, r) => return Some(Divergence {
+                step,
+                left: l.map(str::to_string),
+                right: r.map(str::to_string)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_flags() {
+        assert_eq!(TraceFlags::parse("exec,scan"), Ok(TraceFlags { scan: true, exec: true }));
+        assert_eq!(TraceFlags::parse("scan"), Ok(TraceFlags { scan: true, exec: false }));
+        assert_eq!(TraceFlags::parse(""), Ok(TraceFlags::default()));
+        assert!(TraceFlags::parse("exec,gc").is_err());
+    }
+
+    #[test]
+    fn diff_finds_first_divergence() {
+        let a = "{\"offset\":0,\"op\":\"OP_NIL\",\"operands\":[],\"line\":1,\"stack\":[]}\n\
+                 {\"offset\":1,\"op\":\"OP_RETURN\",\"operands\":[],\"line\":1,\"stack\":[\"nil\"]}\n";
+        // the same steps at different offsets still match
+        let moved = a.replace("\"offset\":1", "\"offset\":7");
+        assert_eq!(diff(a, &moved), None);
+
+        let changed = a.replace("[\"nil\"]", "[\"true\"]");
+        let divergence = diff(a, &changed).unwrap();
+        assert_eq!(divergence.step, 2);
+        assert!(divergence.right.unwrap().contains("true"));
+
+        // constant indexes may differ, but the argument counts may not
+        let call = "{\"offset\":4,\"op\":\"OP_CALL\",\"operands\":[1],\"count\":1,\"line\":1,\"stack\":[]}";
+        assert_eq!(diff(call, &call.replace("[1]", "[3]")), None);
+        assert_eq!(diff(call, &call.replace("\"count\":1", "\"count\":2")).unwrap().step, 1);
+
+        let short = a.lines().next().unwrap();
+        let divergence = diff(a, short).unwrap();
+        assert_eq!(divergence.step, 2);
+        assert_eq!(divergence.right, None);
+    }
+}
@@ -0,0 +1,84 @@
+//! Rust structs exposed to scripts as classes, usually through
+//! `#[derive(LoxClass)]` and `#[lox_methods]` from `rustilox_derive`
+
+use std::cell::{Ref, RefCell, RefMut};
+
+use errors::LoxError;
+use values::Value;
+use values::host::HostObject;
+use values::native::Native;
+
+/// The fields of a struct that scripts can read and assign
+pub trait LoxClass: 'static {
+    /// The class name scripts use to construct it
+    const NAME: &'static str;
+
+    /// The field `name`, or `None` if there isn't one
+    fn get_field(&self, name: &str) -> Option<Value>;
+
+    /// Assign the field `name`, or `None` if there isn't one scripts can assign
+    fn set_field(&mut self, name: &str, value: Value) -> Option<Result<(), LoxError>>;
+}
+
+/// The constructor and methods of a class
+pub trait LoxMethods: LoxClass + Sized {
+    /// A native that builds an `Instance`, called by the class name
+    fn constructor() -> Native;
+
+    /// Call the method `name` on `instance`, or `None` if there isn't one.
+    /// Only the borrow the method needs is taken, and only while it runs, so
+    /// an instance can be passed to its own methods.
+    fn invoke_method(instance: &Instance<Self>, name: &str, args: Vec<Value>) -> Option<Result<Value, LoxError>>;
+}
+
+/// An instance of a class as scripts see it
+pub struct Instance<T>(RefCell<T>);
+
+impl<T: LoxMethods> Instance<T> {
+    pub fn init(value: T) -> Instance<T> {
+        Instance(RefCell::new(value))
+    }
+
+    /// The Rust struct behind the instance, unless a method is changing it
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, LoxError> {
+        self.0.try_borrow()
+            .map_err(|_| LoxError::RuntimeError(format!("can't use {} while it's being changed", T::NAME), 0))
+    }
+
+    /// The Rust struct behind the instance, unless it's already in use
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, LoxError> {
+        self.0.try_borrow_mut()
+            .map_err(|_| LoxError::RuntimeError(format!("can't change {} while it's in use", T::NAME), 0))
+    }
+}
+
+impl<T: LoxMethods> HostObject for Instance<T> {
+    fn type_name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn class_name() -> &'static str {
+        T::NAME
+    }
+
+    fn get(&self, name: &str) -> Result<Value, LoxError> {
+        match self.try_borrow()?.get_field(name) {
+            Some(value) => Ok(value),
+            None => Err(LoxError::RuntimeError(format!("undefined property '{}' on {}", name, T::NAME), 0))
+        }
+    }
+
+    fn set(&self, name: &str, value: Value) -> Result<(), LoxError> {
+        match self.try_borrow_mut()?.set_field(name, value) {
+            Some(result) => result,
+            None => Err(LoxError::RuntimeError(format!("can't set property '{}' on {}", name, T::NAME), 0))
+        }
+    }
+
+    fn invoke(&self, name: &str, args: Vec<Value>) -> Result<Value, LoxError> {
+        match T::invoke_method(self, name, args) {
+            Some(result) => result,
+            None => Err(LoxError::RuntimeError(format!("undefined method '{}' on {}", name, T::NAME), 0))
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Conversions between `Value` and Rust types, for natives and hosts
+
+use std::any::Any;
+use std::fmt;
+use std::rc::Rc;
+
+use errors::LoxError;
+use values::Value;
+use values::host::HostObject;
+use values::list::List;
+
+/// A value that was the wrong type for what it was converted to
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str
+}
+
+impl ConversionError {
+    fn init(expected: &'static str, value: &Value) -> ConversionError {
+        ConversionError { expected, found: value.type_name() }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.found)
+    }
+}
+
+impl From<ConversionError> for LoxError {
+    fn from(e: ConversionError) -> LoxError {
+        LoxError::RuntimeError(e.to_string(), 0)
+    }
+}
+
+/// Rust types that can be made from a Lox value
+pub trait FromLox: Sized {
+    fn from_lox(value: Value) -> Result<Self, ConversionError>;
+}
+
+/// Rust types that can be turned into a Lox value
+pub trait IntoLox {
+    fn into_lox(self) -> Value;
+}
+
+/// What natives and methods return: a value, or a value or an error
+pub trait IntoLoxResult {
+    fn into_lox_result(self) -> Result<Value, LoxError>;
+}
+
+impl<T: IntoLox> IntoLoxResult for T {
+    fn into_lox_result(self) -> Result<Value, LoxError> {
+        Ok(self.into_lox())
+    }
+}
+
+impl<T: IntoLox> IntoLoxResult for Result<T, LoxError> {
+    fn into_lox_result(self) -> Result<Value, LoxError> {
+        self.map(IntoLox::into_lox)
+    }
+}
+
+impl FromLox for Value {
+    fn from_lox(value: Value) -> Result<Value, ConversionError> {
+        Ok(value)
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: Value) -> Result<f64, ConversionError> {
+        match value {
+            Value::Real(r) => Ok(r),
+            Value::Natural(n) => Ok(n as f64),
+            _ => Err(ConversionError::init("number", &value))
+        }
+    }
+}
+
+impl FromLox for i64 {
+    /// Reals with no fractional part convert too
+    fn from_lox(value: Value) -> Result<i64, ConversionError> {
+        match value {
+            Value::Natural(n) => Ok(n),
+            Value::Real(r) if r.fract() == 0.0 && r >= i64::MIN as f64 && r < i64::MAX as f64 => Ok(r as i64),
+            _ => Err(ConversionError::init("integer", &value))
+        }
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: Value) -> Result<bool, ConversionError> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(ConversionError::init("bool", &value))
+        }
+    }
+}
+
+impl FromLox for () {
+    fn from_lox(value: Value) -> Result<(), ConversionError> {
+        match value {
+            Value::Nil => Ok(()),
+            _ => Err(ConversionError::init("nil", &value))
+        }
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: Value) -> Result<String, ConversionError> {
+        match value {
+            Value::String(s) => Ok(s.to_string()),
+            _ => Err(ConversionError::init("string", &value))
+        }
+    }
+}
+
+/// `nil` is `None`, anything else must convert to `T`
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: Value) -> Result<Option<T>, ConversionError> {
+        match value {
+            Value::Nil => Ok(None),
+            _ => T::from_lox(value).map(Some)
+        }
+    }
+}
+
+/// Any host object, to be inspected through its trait
+impl FromLox for Rc<dyn HostObject> {
+    fn from_lox(value: Value) -> Result<Rc<dyn HostObject>, ConversionError> {
+        match value {
+            Value::Host(host) => Ok(host),
+            _ => Err(ConversionError::init("object", &value))
+        }
+    }
+}
+
+/// A host object of one particular Rust type
+impl<T: HostObject> FromLox for Rc<T> {
+    fn from_lox(value: Value) -> Result<Rc<T>, ConversionError> {
+        if let Value::Host(ref host) = value {
+            let any: Rc<dyn Any> = host.clone();
+            if let Ok(host) = any.downcast::<T>() {
+                return Ok(host);
+            }
+        }
+        Err(ConversionError::init(T::class_name(), &value))
+    }
+}
+
+/// A list whose items all convert to `T`
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(value: Value) -> Result<Vec<T>, ConversionError> {
+        let list = Rc::<List>::from_lox(value)?;
+        list.items().iter().cloned().map(T::from_lox).collect()
+    }
+}
+
+impl IntoLox for Value {
+    fn into_lox(self) -> Value {
+        self
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self) -> Value {
+        Value::Real(self)
+    }
+}
+
+impl IntoLox for i64 {
+    fn into_lox(self) -> Value {
+        Value::Natural(self)
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl IntoLox for String {
+    fn into_lox(self) -> Value {
+        Value::String(Rc::from(self))
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self) -> Value {
+        Value::String(Rc::from(self))
+    }
+}
+
+impl IntoLox for Rc<dyn HostObject> {
+    fn into_lox(self) -> Value {
+        Value::Host(self)
+    }
+}
+
+impl<T: HostObject + 'static> IntoLox for Rc<T> {
+    fn into_lox(self) -> Value {
+        Value::Host(self)
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self) -> Value {
+        match self {
+            Some(value) => value.into_lox(),
+            None => Value::Nil
+        }
+    }
+}
+
+impl<T: IntoLox> IntoLox for Vec<T> {
+    fn into_lox(self) -> Value {
+        Value::Host(Rc::new(List::init(self.into_iter().map(IntoLox::into_lox).collect())))
+    }
+}
+
+// tuples travel as lists of exactly as many items
+macro_rules! tuple_conversions {
+    ($count:literal; $($item:ident),*) => {
+        impl<$($item: FromLox),*> FromLox for ($($item,)*) {
+            fn from_lox(value: Value) -> Result<($($item,)*), ConversionError> {
+                let list = Rc::<List>::from_lox(value)?;
+                if list.items().len() != $count {
+                    return Err(ConversionError { expected: concat!("list of ", $count), found: "list" });
+                }
+                let mut items = list.items().iter().cloned();
+                Ok(($($item::from_lox(items.next().unwrap())?,)*))
+            }
+        }
+
+        impl<$($item: IntoLox),*> IntoLox for ($($item,)*) {
+            #[allow(non_snake_case)]
+            fn into_lox(self) -> Value {
+                let ($($item,)*) = self;
+                Value::Host(Rc::new(List::init(vec![$($item.into_lox()),*])))
+            }
+        }
+    };
+}
+
+tuple_conversions!(2; A, B);
+tuple_conversions!(3; A, B, C);
+tuple_conversions!(4; A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_between_values_and_rust() {
+        assert_eq!(f64::from_lox(Value::Natural(2)), Ok(2.0));
+        assert_eq!(i64::from_lox(Value::Real(3.0)), Ok(3));
+        assert_eq!(i64::from_lox(Value::Real(3.5)).unwrap_err().to_string(), "expected integer, got number");
+        assert_eq!(f64::from_lox(Value::Boolean(true)).unwrap_err().to_string(), "expected number, got bool");
+        assert_eq!(String::from_lox("lox".into_lox()), Ok("lox".to_string()));
+        assert_eq!(Option::<bool>::from_lox(Value::Nil), Ok(None));
+        assert_eq!(Option::<bool>::from_lox(Value::Boolean(false)), Ok(Some(false)));
+        assert_eq!(<()>::from_lox(Value::Natural(0)).unwrap_err().found, "number");
+
+        assert_eq!(None::<f64>.into_lox(), Value::Nil);
+        assert_eq!(Some(1.5).into_lox(), Value::Real(1.5));
+        assert_eq!(().into_lox(), Value::Nil);
+    }
+
+    #[test]
+    fn converts_vecs_and_tuples_through_lists() {
+        let list = vec![1i64, 2, 3].into_lox();
+        assert_eq!(list.type_name(), "list");
+        assert_eq!(list.to_string(), "[1, 2, 3]");
+        assert_eq!(Vec::<i64>::from_lox(list.clone()), Ok(vec![1, 2, 3]));
+        assert_eq!(Vec::<bool>::from_lox(list.clone()).unwrap_err().to_string(), "expected bool, got number");
+        assert_eq!(Vec::<i64>::from_lox(Value::Nil).unwrap_err().to_string(), "expected list, got nil");
+
+        let pair = (1.5, "a").into_lox();
+        assert_eq!(pair.to_string(), "[1.5, a]");
+        assert_eq!(<(f64, String)>::from_lox(pair.clone()), Ok((1.5, "a".to_string())));
+        assert_eq!(<(f64, String, bool)>::from_lox(pair).unwrap_err().to_string(), "expected list of 3, got list");
+        assert_eq!(<(i64, i64)>::from_lox(list).unwrap_err().expected, "list of 2");
+    }
+}
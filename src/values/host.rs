@@ -0,0 +1,53 @@
+//! Rust objects handed to scripts, whose properties and methods are
+//! implemented by the host
+
+use std::any::Any;
+use std::fmt;
+
+use errors::LoxError;
+use values::Value;
+
+/// An object owned by the host program. Scripts share it through an `Rc`, so
+/// anything `set` or a method changes has to live behind a `Cell` or `RefCell`.
+///
+/// Errors are reported as `LoxError::RuntimeError`; the VM fills in the line.
+/// Natives get the concrete type back by taking an `Rc<T>` argument.
+pub trait HostObject: Any {
+    /// The name scripts see in error messages, such as "File"
+    fn type_name(&self) -> &'static str;
+
+    /// The same name, for when there's no instance to ask, such as when a
+    /// value of some other type was passed where this one was expected.
+    /// Defaults to the name of the Rust type.
+    fn class_name() -> &'static str where Self: Sized {
+        let name = ::std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// Read the property `name`
+    fn get(&self, name: &str) -> Result<Value, LoxError> {
+        Err(LoxError::RuntimeError(format!("undefined property '{}' on {}", name, self.type_name()), 0))
+    }
+
+    /// Assign `value` to the property `name`
+    fn set(&self, name: &str, _value: Value) -> Result<(), LoxError> {
+        Err(LoxError::RuntimeError(format!("can't set property '{}' on {}", name, self.type_name()), 0))
+    }
+
+    /// Call the method `name`
+    fn invoke(&self, name: &str, _args: Vec<Value>) -> Result<Value, LoxError> {
+        Err(LoxError::RuntimeError(format!("undefined method '{}' on {}", name, self.type_name()), 0))
+    }
+
+    /// How the object prints
+    fn display(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.type_name())
+    }
+}
+
+impl fmt::Debug for dyn HostObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HostObject({})", self.type_name())
+    }
+}
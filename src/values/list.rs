@@ -0,0 +1,66 @@
+//! A fixed sequence of values, which is how Rust `Vec`s and tuples reach
+//! scripts until the language has lists of its own
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use errors::LoxError;
+use values::Value;
+use values::convert::FromLox;
+use values::host::HostObject;
+use values::native::check_arity;
+
+/// Scripts read its `length` and call `get(index)` on it
+pub struct List {
+    items: Vec<Value>
+}
+
+impl List {
+    pub fn init(items: Vec<Value>) -> List {
+        List { items }
+    }
+
+    pub fn items(&self) -> &[Value] {
+        &self.items
+    }
+}
+
+impl HostObject for List {
+    fn type_name(&self) -> &'static str {
+        "list"
+    }
+
+    fn class_name() -> &'static str {
+        "list"
+    }
+
+    fn get(&self, name: &str) -> Result<Value, LoxError> {
+        match name {
+            "length" => Ok(Value::Natural(self.items.len() as i64)),
+            _ => Err(LoxError::RuntimeError(format!("undefined property '{}' on list", name), 0))
+        }
+    }
+
+    fn invoke(&self, name: &str, args: Vec<Value>) -> Result<Value, LoxError> {
+        if name != "get" {
+            return Err(LoxError::RuntimeError(format!("undefined method '{}' on list", name), 0));
+        }
+
+        check_arity(1, args.len())?;
+        let index = i64::from_lox(args.into_iter().next().unwrap())?;
+        usize::try_from(index).ok()
+            .and_then(|i| self.items.get(i).cloned())
+            .ok_or_else(|| LoxError::RuntimeError(format!("list index {} is out of range", index), 0))
+    }
+
+    fn display(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", item)?;
+        }
+        write!(f, "]")
+    }
+}
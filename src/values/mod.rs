@@ -1,5 +1,51 @@
 pub mod value;
 pub mod valuearray;
+pub mod native;
+pub mod host;
+pub mod class;
+pub mod list;
+pub mod convert;
+#[cfg(feature = "nan_boxing")] pub mod nanbox;
 
-pub use ::values::value::Value;
+pub use ::values::value::{Value, OpError};
 pub use ::values::valuearray::ValueArray;
+pub use ::values::native::{Native, IntoNative};
+pub use ::values::host::HostObject;
+pub use ::values::class::{LoxClass, LoxMethods, Instance};
+pub use ::values::list::List;
+pub use ::values::convert::{FromLox, IntoLox, IntoLoxResult, ConversionError};
+
+/// How the VM stores values on its stack: the `Value` enum itself, or a
+/// packed 64-bit word when the `nan_boxing` feature is enabled
+#[cfg(not(feature = "nan_boxing"))]
+pub type Slot = Value;
+#[cfg(feature = "nan_boxing")]
+pub use ::values::nanbox::NanBox as Slot;
+
+/// Pack a value for the VM's stack
+#[cfg(not(feature = "nan_boxing"))]
+#[inline]
+pub fn to_slot(value: Value) -> Slot {
+    value
+}
+
+/// Pack a value for the VM's stack
+#[cfg(feature = "nan_boxing")]
+#[inline]
+pub fn to_slot(value: Value) -> Slot {
+    Slot::from(value)
+}
+
+/// Unpack a value from the VM's stack
+#[cfg(not(feature = "nan_boxing"))]
+#[inline]
+pub fn from_slot(slot: Slot) -> Value {
+    slot
+}
+
+/// Unpack a value from the VM's stack
+#[cfg(feature = "nan_boxing")]
+#[inline]
+pub fn from_slot(slot: Slot) -> Value {
+    Value::from(slot)
+}
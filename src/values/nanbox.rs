@@ -0,0 +1,306 @@
+use std::cmp;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops;
+use std::rc::Rc;
+
+use values::value::{Value, OpError};
+
+// doubles are stored as-is. anything with all of these bits set is a quiet
+// NaN that no arithmetic produces, leaving the low bits free for a payload
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+// quiet NaNs with the sign bit also set carry a pointer in their low 48 bits
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+const NIL: u64 = QNAN | TAG_NIL;
+const FALSE: u64 = QNAN | TAG_FALSE;
+const TRUE: u64 = QNAN | TAG_TRUE;
+
+// naturals that fit in 48 bits are stored in the low bits of a quiet NaN
+// with this bit set
+const TAG_NATURAL: u64 = 1 << 48;
+const NATURAL_BITS: u64 = TAG_NATURAL - 1;
+const NATURAL_MAX: i64 = (1 << 47) - 1;
+const NATURAL_MIN: i64 = -(1 << 47);
+
+/// A `Value` packed into a single 64-bit word: reals and naturals that fit
+/// in 48 bits are stored inline, `nil`/`true`/`false` are tagged quiet NaNs,
+/// and everything else lives behind an `Rc<Value>` stored in a NaN's payload.
+///
+/// `Rc<str>` is a fat pointer that won't fit in a payload, so strings are
+/// boxed twice and gain nothing from the packing.
+pub struct NanBox(u64, PhantomData<Rc<Value>>);
+
+impl NanBox {
+    fn word(bits: u64) -> NanBox {
+        NanBox(bits, PhantomData)
+    }
+
+    fn real(v: f64) -> NanBox {
+        let bits = v.to_bits();
+        if bits & QNAN == QNAN {
+            // a NaN that collides with our tags; any other NaN will do
+            NanBox::word(CANONICAL_NAN)
+        }
+        else {
+            NanBox::word(bits)
+        }
+    }
+
+    fn natural(n: i64) -> NanBox {
+        if (NATURAL_MIN..=NATURAL_MAX).contains(&n) {
+            NanBox::word(QNAN | TAG_NATURAL | (n as u64 & NATURAL_BITS))
+        }
+        else {
+            NanBox::object(Value::Natural(n))
+        }
+    }
+
+    fn object(value: Value) -> NanBox {
+        let ptr = Rc::into_raw(Rc::new(value)) as u64;
+        debug_assert!(ptr & (SIGN_BIT | QNAN) == 0, "pointer doesn't fit in a NaN payload");
+        NanBox::word(SIGN_BIT | QNAN | ptr)
+    }
+
+    fn is_real(&self) -> bool {
+        self.0 & QNAN != QNAN
+    }
+
+    fn is_natural(&self) -> bool {
+        self.0 & (SIGN_BIT | QNAN | TAG_NATURAL) == QNAN | TAG_NATURAL
+    }
+
+    fn is_object(&self) -> bool {
+        self.0 & (SIGN_BIT | QNAN) == SIGN_BIT | QNAN
+    }
+
+    fn as_real(&self) -> f64 {
+        f64::from_bits(self.0)
+    }
+
+    fn as_natural(&self) -> i64 {
+        // shift the 48-bit value up and back down to restore its sign
+        (((self.0 & NATURAL_BITS) << 16) as i64) >> 16
+    }
+
+    fn as_object(&self) -> *const Value {
+        (self.0 & !(SIGN_BIT | QNAN)) as *const Value
+    }
+
+    /// `nil` and `false` are falsey, everything else is truthy
+    pub fn is_falsey(&self) -> bool {
+        self.0 == NIL || self.0 == FALSE
+    }
+
+    /// Unpack a copy of the value this word represents
+    pub fn to_value(&self) -> Value {
+        if self.is_real() {
+            Value::Real(self.as_real())
+        }
+        else if self.is_natural() {
+            Value::Natural(self.as_natural())
+        }
+        else if self.is_object() {
+            unsafe { (*self.as_object()).clone() }
+        }
+        else {
+            match self.0 {
+                TRUE => Value::Boolean(true),
+                FALSE => Value::Boolean(false),
+                _ => Value::Nil
+            }
+        }
+    }
+}
+
+impl From<Value> for NanBox {
+    fn from(value: Value) -> NanBox {
+        match value {
+            Value::Nil => NanBox::word(NIL),
+            Value::Boolean(true) => NanBox::word(TRUE),
+            Value::Boolean(false) => NanBox::word(FALSE),
+            Value::Real(v) => NanBox::real(v),
+            Value::Natural(n) => NanBox::natural(n),
+            _ => NanBox::object(value)
+        }
+    }
+}
+
+impl From<NanBox> for Value {
+    fn from(packed: NanBox) -> Value {
+        packed.to_value()
+    }
+}
+
+impl Clone for NanBox {
+    fn clone(&self) -> NanBox {
+        if self.is_object() {
+            unsafe { Rc::increment_strong_count(self.as_object()); }
+        }
+        NanBox::word(self.0)
+    }
+}
+
+impl Drop for NanBox {
+    fn drop(&mut self) {
+        if self.is_object() {
+            unsafe { Rc::decrement_strong_count(self.as_object()); }
+        }
+    }
+}
+
+impl fmt::Debug for NanBox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NanBox({:#018x}, {:?})", self.0, self.to_value())
+    }
+}
+
+impl fmt::Display for NanBox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_value())
+    }
+}
+
+impl PartialEq for NanBox {
+    fn eq(&self, rhs: &NanBox) -> bool {
+        if self.is_real() && rhs.is_real() {
+            return self.as_real() == rhs.as_real();
+        }
+        self.to_value() == rhs.to_value()
+    }
+}
+
+impl cmp::PartialOrd for NanBox {
+    fn partial_cmp(&self, rhs: &NanBox) -> Option<cmp::Ordering> {
+        if self.is_real() && rhs.is_real() {
+            return self.as_real().partial_cmp(&rhs.as_real());
+        }
+        self.to_value().partial_cmp(&rhs.to_value())
+    }
+}
+
+impl ops::Neg for NanBox {
+    type Output = Result<NanBox, OpError>;
+
+    fn neg(self) -> Self::Output {
+        if self.is_real() {
+            return Ok(NanBox::real(-self.as_real()));
+        }
+        (-self.to_value()).map(NanBox::from)
+    }
+}
+
+impl ops::Not for NanBox {
+    type Output = Result<NanBox, OpError>;
+
+    fn not(self) -> Self::Output {
+        (!self.to_value()).map(NanBox::from)
+    }
+}
+
+// arithmetic on two reals never leaves the packed representation; anything
+// else defers to `Value` so both representations behave identically
+macro_rules! arithmetic {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl ops::$trait for NanBox {
+            type Output = Result<NanBox, OpError>;
+
+            fn $method(self, rhs: NanBox) -> Self::Output {
+                if self.is_real() && rhs.is_real() {
+                    return Ok(NanBox::real(self.as_real() $op rhs.as_real()));
+                }
+                (self.to_value() $op rhs.to_value()).map(NanBox::from)
+            }
+        }
+    };
+}
+
+arithmetic!(Add, add, +);
+arithmetic!(Sub, sub, -);
+arithmetic!(Mul, mul, *);
+arithmetic!(Div, div, /);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+
+    fn round_trip(value: Value) {
+        assert_eq!(Value::from(NanBox::from(value.clone())), value);
+    }
+
+    #[test]
+    fn fits_in_a_word() {
+        assert_eq!(mem::size_of::<NanBox>(), 8);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(Value::Nil);
+        round_trip(Value::Boolean(true));
+        round_trip(Value::Boolean(false));
+        round_trip(Value::Real(0.0));
+        round_trip(Value::Real(-4.2));
+        round_trip(Value::Real(f64::INFINITY));
+        round_trip(Value::Natural(42));
+        round_trip(Value::Natural(i64::MIN));
+        round_trip(Value::Natural(i64::MAX));
+        round_trip(Value::Natural(-1));
+        round_trip(Value::Natural(NATURAL_MIN));
+        round_trip(Value::Natural(NATURAL_MAX));
+        round_trip(Value::Natural(NATURAL_MAX + 1));
+        round_trip(Value::String(Rc::from("lox")));
+    }
+
+    #[test]
+    fn small_naturals_are_inline() {
+        assert!(NanBox::from(Value::Natural(42)).is_natural());
+        assert!(NanBox::from(Value::Natural(NATURAL_MIN)).is_natural());
+        assert!(NanBox::from(Value::Natural(NATURAL_MAX + 1)).is_object());
+        assert!(!NanBox::from(Value::Nil).is_natural());
+        assert!(!NanBox::from(Value::Real(42.0)).is_natural());
+    }
+
+    #[test]
+    fn nan_stays_a_real() {
+        let nan = NanBox::from(Value::Real(f64::from_bits(QNAN | TAG_TRUE)));
+        match nan.to_value() {
+            Value::Real(v) => assert!(v.is_nan()),
+            other => panic!("expected a NaN real, got {:?}", other)
+        }
+        assert!(NanBox::from(Value::Real(f64::NAN)) != NanBox::from(Value::Real(f64::NAN)));
+    }
+
+    #[test]
+    fn objects_are_shared_between_clones() {
+        let a = NanBox::from(Value::String(Rc::from("lox")));
+        let b = a.clone();
+        assert_eq!(a.as_object(), b.as_object());
+        drop(a);
+        assert_eq!(b.to_value(), Value::String(Rc::from("lox")));
+    }
+
+    #[test]
+    fn operators_match_value() {
+        let values = vec![Value::Nil, Value::Boolean(true), Value::Real(1.5), Value::Real(-2.0), Value::Natural(3), Value::Natural(-3), Value::Natural(i64::MAX)];
+        for a in &values {
+            assert_eq!((-NanBox::from(a.clone())).map(Value::from), -a.clone());
+            assert_eq!((!NanBox::from(a.clone())).map(Value::from), !a.clone());
+            assert_eq!(NanBox::from(a.clone()).is_falsey(), a.is_falsey());
+            for b in &values {
+                let (pa, pb) = (|| NanBox::from(a.clone()), || NanBox::from(b.clone()));
+                assert_eq!((pa() + pb()).map(Value::from), a.clone() + b.clone());
+                assert_eq!((pa() - pb()).map(Value::from), a.clone() - b.clone());
+                assert_eq!((pa() * pb()).map(Value::from), a.clone() * b.clone());
+                assert_eq!((pa() / pb()).map(Value::from), a.clone() / b.clone());
+                assert_eq!(pa() == pb(), a == b);
+                assert_eq!(pa().partial_cmp(&pb()), a.partial_cmp(b));
+            }
+        }
+    }
+}
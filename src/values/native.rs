@@ -0,0 +1,97 @@
+//! Functions written in Rust that scripts can call
+
+use std::fmt;
+
+use errors::LoxError;
+use values::Value;
+use values::convert::{FromLox, IntoLox, IntoLoxResult};
+
+type Function = Box<dyn Fn(Vec<Value>) -> Result<Value, LoxError>>;
+
+/// A Rust function callable from Lox
+pub struct Native {
+    pub name: String,
+    /// how many arguments it takes, or `None` if it takes any number
+    pub arity: Option<usize>,
+    function: Function
+}
+
+impl Native {
+    /// A native taking exactly `arity` arguments
+    pub fn init<F>(name: &str, arity: usize, function: F) -> Native
+        where F: Fn(Vec<Value>) -> Result<Value, LoxError> + 'static {
+        Native {
+            name: name.to_string(),
+            arity: Some(arity),
+            function: Box::new(function)
+        }
+    }
+
+    /// A native taking any number of arguments, all of which convert to `T`
+    pub fn variadic<T, R, F>(name: &str, function: F) -> Native
+        where T: FromLox, R: IntoLox, F: Fn(Vec<T>) -> R + 'static {
+        Native {
+            name: name.to_string(),
+            arity: None,
+            function: Box::new(move |args| {
+                let args = args.into_iter().map(T::from_lox).collect::<Result<Vec<T>, _>>()?;
+                Ok(function(args).into_lox())
+            })
+        }
+    }
+
+    /// Adapt a plain Rust function, such as `f64::hypot`, taking its arity
+    /// from its signature and converting its arguments and result
+    pub fn wrap<Args, F: IntoNative<Args>>(name: &str, function: F) -> Native {
+        function.into_native(name)
+    }
+
+    pub fn call(&self, args: Vec<Value>) -> Result<Value, LoxError> {
+        if let Some(arity) = self.arity {
+            check_arity(arity, args.len())?;
+        }
+        (self.function)(args)
+    }
+}
+
+/// Fail unless a call passed `arity` arguments
+pub fn check_arity(arity: usize, count: usize) -> Result<(), LoxError> {
+    if count != arity {
+        return Err(LoxError::RuntimeError(format!("expected {} arguments but got {}", arity, count), 0));
+    }
+    Ok(())
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Native({})", self.name)
+    }
+}
+
+/// Rust functions that can become natives, with `Args` being the tuple of
+/// their argument types
+pub trait IntoNative<Args> {
+    fn into_native(self, name: &str) -> Native;
+}
+
+macro_rules! into_native {
+    ($count:expr; $($arg:ident),*) => {
+        impl<F, R, $($arg),*> IntoNative<($($arg,)*)> for F
+            where F: Fn($($arg),*) -> R + 'static, R: IntoLoxResult, $($arg: FromLox),* {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_native(self, name: &str) -> Native {
+                Native::init(name, $count, move |args| {
+                    let mut args = args.into_iter();
+                    $(let $arg = $arg::from_lox(args.next().unwrap())?;)*
+                    self($($arg),*).into_lox_result()
+                })
+            }
+        }
+    }
+}
+
+into_native!(0;);
+into_native!(1; A);
+into_native!(2; A, B);
+into_native!(3; A, B, C);
+into_native!(4; A, B, C, D);
@@ -1,101 +1,201 @@
 use std::fmt;
 use std::ops;
 use std::cmp;
+use std::rc::Rc;
 
-#[derive(Debug,Clone,PartialEq)]
+use values::host::HostObject;
+use values::native::Native;
+
+#[derive(Debug,Clone)]
 pub enum Value {
     Nil,
     Real(f64),
-    _Natural(i64),
-    Boolean(bool)
+    Natural(i64),
+    Boolean(bool),
+    String(Rc<str>),
+    Native(Rc<Native>),
+    Host(Rc<dyn HostObject>)
 }
 
 use self::Value::Nil;
 use self::Value::Real;
-use self::Value::_Natural;
+use self::Value::Natural;
 use self::Value::Boolean;
 
+/// Why an operator couldn't produce a value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpError {
+    /// the operands aren't types the operator works on
+    TypeMismatch,
+    /// the result doesn't fit in a natural
+    Overflow,
+    /// a natural was divided by zero
+    DivisionByZero
+}
+
+impl Value {
+    /// `nil` and `false` are falsey, everything else is truthy
+    pub fn is_falsey(&self) -> bool {
+        matches!(*self, Nil | Boolean(false))
+    }
+
+    /// The name of the value's type, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Nil => "nil",
+            Real(_) | Natural(_) => "number",
+            Boolean(_) => "bool",
+            Value::String(_) => "string",
+            Value::Native(_) => "native",
+            Value::Host(ref host) => host.type_name()
+        }
+    }
+
+    /// The value as a string, formatted the same way as `Display`
+    pub fn stringify(self) -> Value {
+        match self {
+            Value::String(_) => self,
+            _ => Value::String(Rc::from(self.to_string()))
+        }
+    }
+}
+
+/// Compare a natural and a real exactly, without the rounding that comes
+/// from converting the natural into a real first
+fn compare_natural_real(n: i64, r: f64) -> Option<cmp::Ordering> {
+    // 2^63, the first real past the end of the i64 range
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+    if r.is_nan() {
+        return None;
+    }
+    if r >= LIMIT {
+        return Some(cmp::Ordering::Less);
+    }
+    if r < -LIMIT {
+        return Some(cmp::Ordering::Greater);
+    }
+
+    let whole = r.trunc();
+    match n.cmp(&(whole as i64)) {
+        cmp::Ordering::Equal => whole.partial_cmp(&r),
+        o => Some(o)
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Nil => write!(f, "nil"),
             Real(v) => {
-                let zero = if v == 0.0 { ".0" } else { "" };
-                write!(f, "{:+}{}", v, zero)
+                // always show a fractional part so reals can't be mistaken
+                // for naturals
+                let point = if v.is_finite() && v.fract() == 0.0 { ".0" } else { "" };
+                write!(f, "{}{}", v, point)
             },
-            _Natural(v) => write!(f, "{:+}", v),
-            Boolean(v) => write!(f, "{}", v)
+            Natural(v) => write!(f, "{}", v),
+            Boolean(v) => write!(f, "{}", v),
+            Value::String(ref v) => write!(f, "{}", v),
+            Value::Native(ref v) => write!(f, "<native fn {}>", v.name),
+            Value::Host(ref v) => v.display(f)
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, rhs: &Value) -> bool {
+        match (self, rhs) {
+            (Nil, Nil) => true,
+            (Real(a), Real(b)) => a == b,
+            (Natural(a), Natural(b)) => a == b,
+            (Natural(a), Real(b)) => compare_natural_real(*a, *b) == Some(cmp::Ordering::Equal),
+            (Real(a), Natural(b)) => compare_natural_real(*b, *a) == Some(cmp::Ordering::Equal),
+            (Boolean(a), Boolean(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Host(a), Value::Host(b)) => Rc::ptr_eq(a, b),
+            _ => false
         }
     }
 }
 
 impl ops::Neg for Value {
-    type Output = Option<Value>;
+    type Output = Result<Value, OpError>;
 
     fn neg(self) -> Self::Output {
         match self {
-            Real(v) => Some(Real(-v)),
-            _Natural(v) => Some(_Natural(-v)),
-            _ => None
+            Real(v) => Ok(Real(-v)),
+            Natural(v) => v.checked_neg().map(Natural).ok_or(OpError::Overflow),
+            _ => Err(OpError::TypeMismatch)
         }
     }
 }
 
 impl ops::Add for Value {
-    type Output = Option<Value>;
+    type Output = Result<Value, OpError>;
 
     fn add(self, rhs:Value) -> Self::Output {
         match (self, rhs) {
-            (Real(l), Real(r)) => Some(Real(l + r)),
-            (_Natural(l), _Natural(r)) => Some(_Natural(l + r)),
-            _ => None
+            (Real(l), Real(r)) => Ok(Real(l + r)),
+            (Natural(l), Natural(r)) => l.checked_add(r).map(Natural).ok_or(OpError::Overflow),
+            (Natural(l), Real(r)) => Ok(Real(l as f64 + r)),
+            (Real(l), Natural(r)) => Ok(Real(l + r as f64)),
+            (Value::String(l), Value::String(r)) => Ok(Value::String(Rc::from(format!("{}{}", l, r)))),
+            _ => Err(OpError::TypeMismatch)
         }
     }
 }
 
 impl ops::Sub for Value {
-    type Output = Option<Value>;
+    type Output = Result<Value, OpError>;
 
     fn sub(self, rhs:Value) -> Self::Output {
         match (self, rhs) {
-            (Real(l), Real(r)) => Some(Real(l - r)),
-            (_Natural(l), _Natural(r)) => Some(_Natural(l - r)),
-            _ => None
+            (Real(l), Real(r)) => Ok(Real(l - r)),
+            (Natural(l), Natural(r)) => l.checked_sub(r).map(Natural).ok_or(OpError::Overflow),
+            (Natural(l), Real(r)) => Ok(Real(l as f64 - r)),
+            (Real(l), Natural(r)) => Ok(Real(l - r as f64)),
+            _ => Err(OpError::TypeMismatch)
         }
     }
 }
 
 impl ops::Mul for Value {
-    type Output = Option<Value>;
+    type Output = Result<Value, OpError>;
 
     fn mul(self, rhs:Value) -> Self::Output {
         match (self, rhs) {
-            (Real(l), Real(r)) => Some(Real(l * r)),
-            (_Natural(l), _Natural(r)) => Some(_Natural(l * r)),
-            _ => None
+            (Real(l), Real(r)) => Ok(Real(l * r)),
+            (Natural(l), Natural(r)) => l.checked_mul(r).map(Natural).ok_or(OpError::Overflow),
+            (Natural(l), Real(r)) => Ok(Real(l as f64 * r)),
+            (Real(l), Natural(r)) => Ok(Real(l * r as f64)),
+            _ => Err(OpError::TypeMismatch)
         }
     }
 }
 
 impl ops::Div for Value {
-    type Output = Option<Value>;
+    type Output = Result<Value, OpError>;
 
     fn div(self, rhs:Value) -> Self::Output {
         match (self, rhs) {
-            (Real(l), Real(r)) => Some(Real(l / r)),
-            (_Natural(l), _Natural(r)) => Some(_Natural(l / r)),
-            _ => None
+            (Real(l), Real(r)) => Ok(Real(l / r)),
+            (Natural(_), Natural(0)) => Err(OpError::DivisionByZero),
+            (Natural(l), Natural(r)) => l.checked_div(r).map(Natural).ok_or(OpError::Overflow),
+            (Natural(l), Real(r)) => Ok(Real(l as f64 / r)),
+            (Real(l), Natural(r)) => Ok(Real(l / r as f64)),
+            _ => Err(OpError::TypeMismatch)
         }
     }
 }
 
 impl ops::Not for Value {
-    type Output = Option<Value>;
+    type Output = Result<Value, OpError>;
 
     fn not(self) -> Self::Output {
         match self {
-            Boolean(v) => Some(Boolean(!v)),
-            _ => None
+            Boolean(v) => Ok(Boolean(!v)),
+            _ => Err(OpError::TypeMismatch)
         }
     }
 }
@@ -105,7 +205,9 @@ impl cmp::PartialOrd for Value {
         match (self, rhs) {
             (Nil, Nil) => Some(cmp::Ordering::Equal),
             (Real(a), Real(b)) => Some(a.partial_cmp(b)?),
-            (_Natural(a), _Natural(b)) => Some(a.partial_cmp(b)?),
+            (Natural(a), Natural(b)) => Some(a.partial_cmp(b)?),
+            (Natural(a), Real(b)) => compare_natural_real(*a, *b),
+            (Real(a), Natural(b)) => compare_natural_real(*b, *a).map(cmp::Ordering::reverse),
             (Boolean(a), Boolean(b)) => Some(a.partial_cmp(b)?),
             _ => None
         }
@@ -118,17 +220,24 @@ mod tests {
 
     #[test]
     fn same_equality() {
-        assert_eq!(_Natural(42), _Natural(42));
-        assert_ne!(_Natural(-1), _Natural(1));
+        assert_eq!(Natural(42), Natural(42));
+        assert_ne!(Natural(-1), Natural(1));
         assert_eq!(Real(4.2), Real(4.2));
         assert_eq!(Nil, Nil);
         assert_eq!(Boolean(true), Boolean(true));
         assert_ne!(Boolean(true), Boolean(false));
+        assert_eq!(Value::String(Rc::from("lox")), Value::String(Rc::from("lox")));
+        assert_ne!(Value::String(Rc::from("lox")), Value::String(Rc::from("Lox")));
     }
 
     #[test]
     fn diff_equality() {
-        assert_ne!(_Natural(1), Real(1.0));
+        assert_eq!(Natural(1), Real(1.0));
+        assert_eq!(Real(-3.0), Natural(-3));
+        assert_ne!(Natural(1), Real(1.5));
+        assert_ne!(Natural(i64::MAX), Real(i64::MAX as f64));
+        assert_ne!(Natural(0), Boolean(false));
+        assert_ne!(Nil, Boolean(false));
     }
 
     #[test]
@@ -137,9 +246,47 @@ mod tests {
         assert!(Boolean(true) > Boolean(false));
     }
 
+    #[test]
+    fn diff_partial_ord() {
+        assert!(Natural(2) > Real(1.5));
+        assert!(Real(1.5) < Natural(2));
+        assert!(Natural(-2) < Real(-1.5));
+        assert!(Natural(3) >= Real(3.0));
+        assert!(Natural(i64::MAX) < Real(i64::MAX as f64));
+        assert_eq!(Natural(1).partial_cmp(&Real(f64::NAN)), None);
+    }
+
     #[test]
     #[should_panic]
     fn diff_partial_ord_panics() {
         assert!(Real(4.2) > Boolean(false));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn promotes_mixed_arithmetic() {
+        assert_eq!(Natural(7) / Natural(2), Ok(Natural(3)));
+        assert_eq!(Natural(7) / Real(2.0), Ok(Real(3.5)));
+        assert_eq!(Real(0.5) + Natural(1), Ok(Real(1.5)));
+        assert_eq!(Natural(2) * Natural(3), Ok(Natural(6)));
+        assert_eq!(Natural(1) + Boolean(true), Err(OpError::TypeMismatch));
+    }
+
+    #[test]
+    fn natural_errors() {
+        assert_eq!(Natural(1) / Natural(0), Err(OpError::DivisionByZero));
+        assert_eq!(Natural(i64::MAX) + Natural(1), Err(OpError::Overflow));
+        assert_eq!(Natural(i64::MIN) - Natural(1), Err(OpError::Overflow));
+        assert_eq!(Natural(i64::MAX) * Natural(2), Err(OpError::Overflow));
+        assert_eq!(Natural(i64::MIN) / Natural(-1), Err(OpError::Overflow));
+        assert_eq!(-Natural(i64::MIN), Err(OpError::Overflow));
+        assert_eq!(Real(1.0) / Real(0.0), Ok(Real(f64::INFINITY)));
+    }
+
+    #[test]
+    fn display_distinguishes_naturals_and_reals() {
+        assert_eq!(format!("{}", Natural(3)), "3");
+        assert_eq!(format!("{}", Real(3.0)), "3.0");
+        assert_eq!(format!("{}", Real(-0.5)), "-0.5");
+        assert_eq!(format!("{}", Natural(-12)), "-12");
+    }
+}
@@ -1,8 +1,8 @@
 use values::value::Value;
 
 pub struct ValueArray {
-    pub capacity: u8,
-    pub count: u8,
+    pub capacity: usize,
+    pub count: usize,
     pub values: Vec<Value>
 }
 
@@ -17,20 +17,30 @@ impl ValueArray {
 
     pub fn build(values: Vec<Value>) -> ValueArray {
         ValueArray {
-            capacity: values.len() as u8,
-            count: values.len() as u8,
+            capacity: values.len(),
+            count: values.len(),
             values
         }
     }
 
     pub fn write(&mut self, value:Value) {
         if self.capacity < self.count + 1 {
-            let old_capacity: usize = self.capacity as usize;
-            self.capacity = ::util::grow_capacity(old_capacity) as u8;
-            self.values.resize(self.capacity as usize, Value::Nil);
+            let old_capacity: usize = self.capacity;
+            self.capacity = ::util::grow_capacity(old_capacity);
+            self.values.resize(self.capacity, Value::Nil);
         }
 
-        self.values[self.count as usize] = value;
+        self.values[self.count] = value;
         self.count += 1;
     }
-}
\ No newline at end of file
+
+    /// Remove the most recently written value, if there is one
+    pub fn pop(&mut self) -> Option<Value> {
+        if self.count == 0 {
+            return None;
+        }
+
+        self.count -= 1;
+        Some(::std::mem::replace(&mut self.values[self.count], Value::Nil))
+    }
+}
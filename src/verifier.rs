@@ -0,0 +1,185 @@
+use std::convert::TryFrom;
+
+use chunk::Chunk;
+use opcodes::{OpCode, Operands};
+use errors::LoxError;
+use values::Value;
+
+/// Walk a chunk's bytecode using the opcode metadata table and make sure
+/// the VM could execute it without decoding garbage, reading constants that
+/// don't exist, underflowing the stack, or running off the end of the code
+pub fn verify(chunk: &Chunk) -> Result<(), LoxError> {
+    // first pass: decode every instruction and note where each one starts
+    let mut boundaries: Vec<bool> = vec![false; chunk.count];
+    let mut offset: usize = 0;
+    while offset < chunk.count {
+        let line = chunk.lines[offset];
+        let op = match OpCode::try_from(chunk.code[offset]) {
+            Ok(op) => op,
+            Err(byte) => return Err(LoxError::CompileError(format!("unknown opcode {:04} at offset {:04}", byte, offset), line))
+        };
+        let info = op.info();
+
+        if offset + info.size() > chunk.count {
+            return Err(LoxError::CompileError(format!("truncated operands for {} at offset {:04}", info.mnemonic, offset), line));
+        }
+
+        match info.operands {
+            Operands::None | Operands::Jump | Operands::Count => (),
+            Operands::Constant | Operands::Name | Operands::Invoke => {
+                let constant = chunk.code[offset + 1];
+                if constant as usize >= chunk.constants.count {
+                    return Err(LoxError::CompileError(format!("{} at offset {:04} refers to missing constant {:04}", info.mnemonic, offset, constant), line));
+                }
+                // the VM looks names up as strings
+                let is_string = matches!(chunk.constants.values[constant as usize], Value::String(_));
+                if info.operands != Operands::Constant && !is_string {
+                    return Err(LoxError::CompileError(format!("{} at offset {:04} names non-string constant {:04}", info.mnemonic, offset, constant), line));
+                }
+            }
+        }
+
+        boundaries[offset] = true;
+        offset += info.size();
+    }
+
+    // second pass: follow every path through the code, tracking how deep the
+    // stack is at each instruction
+    let mut depths: Vec<Option<usize>> = vec![None; chunk.count];
+    let mut pending: Vec<(usize, usize)> = vec![(0, 0)];
+    while let Some((offset, depth)) = pending.pop() {
+        if offset >= chunk.count {
+            let line = chunk.lines.get(chunk.count.saturating_sub(1)).cloned().unwrap_or(0);
+            return Err(LoxError::CompileError("execution runs off the end of the chunk".to_string(), line));
+        }
+
+        let line = chunk.lines[offset];
+        if !boundaries[offset] {
+            return Err(LoxError::CompileError(format!("jump into the middle of an instruction at offset {:04}", offset), line));
+        }
+        match depths[offset] {
+            Some(d) if d == depth => continue,
+            Some(d) => return Err(LoxError::CompileError(format!("inconsistent stack depth ({} vs {}) at offset {:04}", d, depth, offset), line)),
+            None => depths[offset] = Some(depth)
+        }
+
+        let op = match OpCode::try_from(chunk.code[offset]) {
+            Ok(op) => op,
+            Err(byte) => return Err(LoxError::CompileError(format!("unknown opcode {:04} at offset {:04}", byte, offset), line))
+        };
+        let info = op.info();
+
+        let pops = match info.operands {
+            Operands::Count => info.pops + chunk.code[offset + 1] as usize,
+            Operands::Invoke => info.pops + chunk.code[offset + 2] as usize,
+            _ => info.pops
+        };
+        if depth < pops {
+            return Err(LoxError::CompileError(format!("stack underflow in {} at offset {:04}", info.mnemonic, offset), line));
+        }
+        let depth = depth - pops + info.pushes;
+
+        if info.operands == Operands::Jump {
+            pending.push((chunk.jump_target(offset), depth));
+        }
+        if op.falls_through() {
+            pending.push((offset + info.size(), depth));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use values::{Value, ValueArray};
+
+    #[test]
+    fn accepts_well_formed_chunk() {
+        let chunk = Chunk::build(
+            ValueArray::build(vec![Value::Real(1.2), Value::Real(3.4)]),
+            vec![OpCode::Constant as u8, 0, OpCode::Constant as u8, 1, OpCode::Add as u8, OpCode::Return as u8],
+            vec![1, 1, 1, 1, 1, 1]
+        );
+        assert!(verify(&chunk).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_constant() {
+        let chunk = Chunk::build(
+            ValueArray::build(vec![Value::Real(1.2)]),
+            vec![OpCode::Constant as u8, 1, OpCode::Return as u8],
+            vec![1, 1, 1]
+        );
+        assert!(verify(&chunk).is_err());
+    }
+
+    #[test]
+    fn rejects_non_string_names() {
+        let chunk = Chunk::build(
+            ValueArray::build(vec![Value::Real(1.2)]),
+            vec![OpCode::GetGlobal as u8, 0, OpCode::Return as u8],
+            vec![1, 1, 1]
+        );
+        assert!(verify(&chunk).is_err());
+
+        let chunk = Chunk::build(
+            ValueArray::build(vec![Value::String("a".into()), Value::Real(1.2)]),
+            vec![OpCode::GetGlobal as u8, 0, OpCode::Invoke as u8, 1, 0, OpCode::Return as u8],
+            vec![1, 1, 1, 1, 1, 1]
+        );
+        assert!(verify(&chunk).is_err());
+    }
+
+    #[test]
+    fn rejects_stack_underflow() {
+        let chunk = Chunk::build(
+            ValueArray::build(vec![Value::Real(1.2)]),
+            vec![OpCode::Constant as u8, 0, OpCode::Add as u8, OpCode::Return as u8],
+            vec![1, 1, 1, 1]
+        );
+        assert!(verify(&chunk).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_and_unterminated_code() {
+        let truncated = Chunk::build(ValueArray::init(), vec![OpCode::Constant as u8], vec![1]);
+        assert!(verify(&truncated).is_err());
+
+        let unterminated = Chunk::build(
+            ValueArray::build(vec![Value::Nil]),
+            vec![OpCode::Constant as u8, 0],
+            vec![1, 1]
+        );
+        assert!(verify(&unterminated).is_err());
+
+        let unknown = Chunk::build(ValueArray::init(), vec![0xff, OpCode::Return as u8], vec![1, 1]);
+        assert!(verify(&unknown).is_err());
+    }
+
+    #[test]
+    fn follows_jumps() {
+        // true and false
+        let chunk = Chunk::build(
+            ValueArray::init(),
+            vec![OpCode::True as u8, OpCode::JumpIfFalse as u8, 0, 2, OpCode::Pop as u8, OpCode::False as u8, OpCode::Return as u8],
+            vec![1, 1, 1, 1, 1, 1, 1]
+        );
+        assert!(verify(&chunk).is_ok());
+
+        let misaligned = Chunk::build(
+            ValueArray::init(),
+            vec![OpCode::Nil as u8, OpCode::Jump as u8, 0, 1, OpCode::JumpIfFalse as u8, 0, 0, OpCode::Return as u8],
+            vec![1, 1, 1, 1, 1, 1, 1, 1]
+        );
+        assert!(verify(&misaligned).is_err());
+
+        let unbalanced = Chunk::build(
+            ValueArray::init(),
+            vec![OpCode::True as u8, OpCode::JumpIfFalse as u8, 0, 1, OpCode::True as u8, OpCode::Return as u8],
+            vec![1, 1, 1, 1, 1, 1]
+        );
+        assert!(verify(&unbalanced).is_err());
+    }
+}
@@ -1,179 +1,346 @@
-use std::cmp;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::rc::Rc;
 
 use chunk::Chunk;
-use opcodes::*;
-use values::Value;
+use opcodes::OpCode;
+use values::{Value, OpError, Slot, to_slot, from_slot};
 use errors::LoxError;
+use trace::Tracer;
+
+/// Global variables by name
+pub type Globals = HashMap<Rc<str>, Value>;
+
+/// The deepest the value stack can get before execution is aborted
+pub const STACK_MAX: usize = 256;
 
 pub struct VM<'a> {
     pub chunk: &'a Chunk,
     ip: usize,
-    stack: Vec<Value>
+    /// allocated up front with `STACK_MAX` slots and never resized; only the
+    /// slots below `stack_top` are live
+    stack: Vec<Slot>,
+    stack_top: usize,
+    tracer: Option<&'a mut dyn Tracer>,
+    globals: Globals,
+    /// where the program's output goes
+    output: Box<dyn Write + 'a>,
+    /// where runtime errors are reported, in addition to being returned
+    diagnostics: Box<dyn Write + 'a>
 }
 
 impl<'a> VM<'a> {
-    pub fn init(chunk: &Chunk) -> VM {
+    pub fn init(chunk: &'a Chunk) -> VM<'a> {
         VM {
             chunk,
             ip: 0,
-            stack: Vec::new()
+            stack: vec![to_slot(Value::Nil); STACK_MAX],
+            stack_top: 0,
+            tracer: None,
+            globals: Globals::new(),
+            output: Box::new(io::stdout()),
+            diagnostics: Box::new(io::sink())
         }
     }
 
+    /// Write the program's output to `output` instead of stdout
+    pub fn set_output(&mut self, output: Box<dyn Write + 'a>) {
+        self.output = output;
+    }
+
+    /// Report runtime errors to `diagnostics`; by default they're only returned
+    pub fn set_diagnostics(&mut self, diagnostics: Box<dyn Write + 'a>) {
+        self.diagnostics = diagnostics;
+    }
+
+    /// Run with `globals` already defined, such as those left by a previous run
+    pub fn set_globals(&mut self, globals: Globals) {
+        self.globals = globals;
+    }
+
+    /// The globals as they were left, so they can be handed to the next run
+    pub fn take_globals(&mut self) -> Globals {
+        mem::take(&mut self.globals)
+    }
+
+    /// Report every instruction executed from now on to `tracer`
+    pub fn set_tracer(&mut self, tracer: &'a mut dyn Tracer) {
+        self.tracer = Some(tracer);
+    }
+
     fn read_byte(&mut self) -> u8 {
         self.ip += 1;
         self.chunk.code[self.ip - 1]
     }
 
-    fn read_constant(&mut self) -> &Value {
-        let loc = self.read_byte();
-        &self.chunk.constants.values[loc as usize]
+    /// Read a constant operand naming a variable or property, which `verify`
+    /// makes sure is a string
+    fn read_name(&mut self) -> Rc<str> {
+        let constant = self.read_byte();
+        match self.chunk.constants.values[constant as usize] {
+            Value::String(ref name) => name.clone(),
+            ref value => Rc::from(value.to_string())
+        }
+    }
+
+    fn read_short(&mut self) -> u16 {
+        self.ip += 2;
+        self.chunk.read_short(self.ip - 2)
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn error(&self, msg: &str) -> LoxError {
+        LoxError::RuntimeError(msg.to_string(), self.chunk.lines[self.ip - 1])
+    }
+
+    /// Give an error from a native or host object the current line
+    #[cold]
+    #[inline(never)]
+    fn host_error(&self, err: LoxError) -> LoxError {
+        match err {
+            LoxError::RuntimeError(msg, _) => self.error(&msg),
+            e => e
+        }
+    }
+
+    /// Report a failed operator, using `msg` when the operands had the wrong types
+    #[cold]
+    #[inline(never)]
+    fn op_error(&self, err: OpError, msg: &str) -> LoxError {
+        match err {
+            OpError::TypeMismatch => self.error(msg),
+            OpError::Overflow => self.error("integer overflow"),
+            OpError::DivisionByZero => self.error("division by zero")
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, value: Slot) -> Result<(), LoxError> {
+        if self.stack_top == STACK_MAX {
+            return Err(self.error("stack overflow"));
+        }
+
+        self.stack[self.stack_top] = value;
+        self.stack_top += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Result<Slot, LoxError> {
+        if self.stack_top == 0 {
+            return Err(self.error("stack underflow"));
+        }
+
+        self.stack_top -= 1;
+        Ok(mem::replace(&mut self.stack[self.stack_top], to_slot(Value::Nil)))
+    }
+
+    /// Replace the value on top of the stack with `op` applied to it
+    #[inline]
+    fn unary_op<F>(&mut self, op: F, msg: &str) -> Result<(), LoxError>
+        where F: FnOnce(Slot) -> Result<Slot, OpError> {
+        if self.stack_top == 0 {
+            return Err(self.error("stack underflow"));
+        }
+
+        let slot = self.stack_top - 1;
+        let a = mem::replace(&mut self.stack[slot], to_slot(Value::Nil));
+        match op(a) {
+            Ok(v) => {
+                self.stack[slot] = v;
+                Ok(())
+            },
+            Err(e) => Err(self.op_error(e, msg))
+        }
     }
 
-    fn binary_op(&mut self) -> Result<(Value, Value), LoxError> {
-        let b = match self.stack.pop() {
-            Some(v) => v,
-            None => return Err(LoxError::CompileError("stack underflow".to_string(), self.chunk.lines[self.ip - 1]))
-        };
-        let a = match self.stack.pop() {
-            Some(v) => v,
-            None => return Err(LoxError::CompileError("stack underflow".to_string(), self.chunk.lines[self.ip - 1]))
-        };
+    /// Pop the right operand and replace the left operand with the result of
+    /// `op`, so the stack only ever shrinks by one slot
+    #[inline]
+    fn binary_op<F>(&mut self, op: F, msg: &str) -> Result<(), LoxError>
+        where F: FnOnce(Slot, Slot) -> Result<Slot, OpError> {
+        if self.stack_top < 2 {
+            return Err(self.error("stack underflow"));
+        }
 
-        Ok((a, b))
+        self.stack_top -= 1;
+        let b = mem::replace(&mut self.stack[self.stack_top], to_slot(Value::Nil));
+        let slot = self.stack_top - 1;
+        let a = mem::replace(&mut self.stack[slot], to_slot(Value::Nil));
+        match op(a, b) {
+            Ok(v) => {
+                self.stack[slot] = v;
+                Ok(())
+            },
+            Err(e) => Err(self.op_error(e, msg))
+        }
     }
 
     pub fn evaluate(&mut self) -> Result<Value, LoxError> {
+        let result = self.run();
+        if let Err(LoxError::RuntimeError(ref msg, line)) = result {
+            // clox's format, which test runners match against
+            let _ = writeln!(self.diagnostics, "{}\n[line {}] in script", msg, line);
+        }
+        result
+    }
+
+    fn run(&mut self) -> Result<Value, LoxError> {
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.on_call("script", 0);
+        }
+
         loop {
-            if cfg!(feature = "trace_execution") {
-                print!("          ");
-                for value in &self.stack {
-                    print!("[ {} ]", value);
-                }
-                println!();
-                print!("{}", self.chunk.get_instruction(self.ip));
+            if let Some(ref mut tracer) = self.tracer {
+                let stack: Vec<Value> = self.stack[..self.stack_top].iter().cloned().map(from_slot).collect();
+                tracer.before_instruction(self.chunk, self.ip, &stack);
             }
 
-            let instruction = self.read_byte();
+            let instruction = match OpCode::try_from(self.read_byte()) {
+                Ok(op) => op,
+                Err(byte) => return Err(LoxError::CompileError(format!("unknown opcode {:04}", byte), self.chunk.lines[self.ip - 1]))
+            };
             match instruction {
-                OP_RETURN => {
-                    let top = match self.stack.pop() {
-                        Some(v) => v,
-                        None => Value::Nil
-                    };
-                    println!("{}", top);
+                OpCode::Return => {
+                    let top = if self.stack_top > 0 { from_slot(self.pop()?) } else { Value::Nil };
+                    if let Err(e) = writeln!(self.output, "{}", top) {
+                        return Err(self.error(&format!("can't write output: {}", e)));
+                    }
 
+                    if let Some(ref mut tracer) = self.tracer {
+                        tracer.on_return(&top);
+                    }
                     return Ok(top);
                 },
-                OP_CONSTANT => {
-                    let new_constant:Value;
-                    {
-                        let constant = self.read_constant();
-                        new_constant = constant.clone();
-                    }
-                    self.stack.push(new_constant);
+                OpCode::Constant => {
+                    let constant = self.read_byte();
+                    let value = self.chunk.constants.values[constant as usize].clone();
+                    self.push(to_slot(value))?;
                 },
+                OpCode::Nil => self.push(to_slot(Value::Nil))?,
+                OpCode::True => self.push(to_slot(Value::Boolean(true)))?,
+                OpCode::False => self.push(to_slot(Value::Boolean(false)))?,
 
-                OP_NEGATE => {
-                    let top = match self.stack.pop() {
-                        Some(v) => v,
-                        None => return Err(LoxError::RuntimeError("stack underflow".to_string(), self.chunk.lines[self.ip - 1]))
-                    };
-                    match -top {
-                        Some(v) => self.stack.push(v),
-                        None => return Err(LoxError::RuntimeError("can't negate a non-numeric value".to_string(), self.chunk.lines[self.ip - 1]))
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    let value = match self.globals.get(&name) {
+                        Some(value) => value.clone(),
+                        None => return Err(self.error(&format!("undefined variable '{}'", name)))
                     };
+                    self.push(to_slot(value))?;
                 },
-                OP_ADD => {
-                    let (a, b) = self.binary_op()?;
-                    match a + b {
-                        Some(v) => self.stack.push(v),
-                        None => return Err(LoxError::RuntimeError("can't add values of differing types".to_string(), self.chunk.lines[self.ip - 1]))
-                    };
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    if self.stack_top == 0 {
+                        return Err(self.error("stack underflow"));
+                    }
+                    // assignment is an expression, so the value stays on the stack
+                    let value = from_slot(self.stack[self.stack_top - 1].clone());
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => return Err(self.error(&format!("undefined variable '{}'", name)))
+                    }
                 },
-                OP_SUBTRACT => {
-                    let (a, b) = self.binary_op()?;
-                    match a - b {
-                        Some(v) => self.stack.push(v),
-                        None => return Err(LoxError::RuntimeError("can't subtract values of differing types".to_string(), self.chunk.lines[self.ip - 1]))
+
+                OpCode::Call => {
+                    let count = self.read_byte() as usize;
+                    if self.stack_top < count + 1 {
+                        return Err(self.error("stack underflow"));
+                    }
+                    let callee = self.stack_top - count - 1;
+                    let native = match from_slot(self.stack[callee].clone()) {
+                        Value::Native(native) => native,
+                        _ => return Err(self.error("can only call functions"))
                     };
+                    let args: Vec<Value> = self.stack[callee + 1..self.stack_top].iter().cloned().map(from_slot).collect();
+                    let result = native.call(args).map_err(|e| self.host_error(e))?;
+                    while self.stack_top > callee + 1 {
+                        self.pop()?;
+                    }
+                    self.stack[callee] = to_slot(result);
                 },
-                OP_MULTIPLY => {
-                    let (a, b) = self.binary_op()?;
-                    match a * b {
-                        Some(v) => self.stack.push(v),
-                        None => return Err(LoxError::RuntimeError("can't multiply values of differing types".to_string(), self.chunk.lines[self.ip - 1]))
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let host = match from_slot(self.pop()?) {
+                        Value::Host(host) => host,
+                        _ => return Err(self.error("only objects have properties"))
                     };
+                    let value = host.get(&name).map_err(|e| self.host_error(e))?;
+                    self.push(to_slot(value))?;
                 },
-                OP_DIVIDE => {
-                    let (a, b) = self.binary_op()?;
-                    match a / b {
-                        Some(v) => self.stack.push(v),
-                        None => return Err(LoxError::RuntimeError("can't divide values of differing types".to_string(), self.chunk.lines[self.ip - 1]))
+                OpCode::SetProperty => {
+                    let name = self.read_name();
+                    let value = self.pop()?;
+                    let host = match from_slot(self.pop()?) {
+                        Value::Host(host) => host,
+                        _ => return Err(self.error("only objects have properties"))
                     };
+                    host.set(&name, from_slot(value.clone())).map_err(|e| self.host_error(e))?;
+                    self.push(value)?;
                 },
-                OP_NOT => {
-                    let top = match self.stack.pop() {
-                        Some(v) => v,
-                        None => return Err(LoxError::RuntimeError("stack underflow".to_string(), self.chunk.lines[self.ip - 1]))
-                    };
-                    match !top {
-                        Some(v) => self.stack.push(v),
-                        None => return Err(LoxError::RuntimeError("can't ! a non-boolean value".to_string(), self.chunk.lines[self.ip - 1]))
+                OpCode::Invoke => {
+                    let name = self.read_name();
+                    let count = self.read_byte() as usize;
+                    if self.stack_top < count + 1 {
+                        return Err(self.error("stack underflow"));
+                    }
+                    let receiver = self.stack_top - count - 1;
+                    let host = match from_slot(self.stack[receiver].clone()) {
+                        Value::Host(host) => host,
+                        _ => return Err(self.error("only objects have methods"))
                     };
+                    let args: Vec<Value> = self.stack[receiver + 1..self.stack_top].iter().cloned().map(from_slot).collect();
+                    let result = host.invoke(&name, args).map_err(|e| self.host_error(e))?;
+                    while self.stack_top > receiver + 1 {
+                        self.pop()?;
+                    }
+                    self.stack[receiver] = to_slot(result);
                 },
-
-                OP_EQUAL => {
-                    let (a, b) = self.binary_op()?;
-                    self.stack.push(Value::Boolean(a == b));
-                },
-                OP_NOTEQUAL => {
-                    let (a, b) = self.binary_op()?;
-                    self.stack.push(Value::Boolean(a != b));
-                },
-                OP_GREATER => {
-                    let (a, b) = self.binary_op()?;
-                    self.stack.push(match a.partial_cmp(&b) {
-                        Some(o) => match o {
-                            cmp::Ordering::Greater => Value::Boolean(true),
-                            _ => Value::Boolean(false)
-                        },
-                        None => return Err(LoxError::RuntimeError("can't compare values of differing types".to_string(), self.chunk.lines[self.ip - 1]))
-                    });
-                },
-                OP_GREATEREQUAL => {
-                    let (a, b) = self.binary_op()?;
-                    self.stack.push(match a.partial_cmp(&b) {
-                        Some(o) => match o {
-                            cmp::Ordering::Less => Value::Boolean(false),
-                            _ => Value::Boolean(true)
-                        },
-                        None => return Err(LoxError::RuntimeError("can't compare values of differing types".to_string(), self.chunk.lines[self.ip - 1]))
-                    });
+                OpCode::Pop => {
+                    self.pop()?;
                 },
-                OP_LESSER => {
-                    let (a, b) = self.binary_op()?;
-                    self.stack.push(match a.partial_cmp(&b) {
-                        Some(o) => match o {
-                            cmp::Ordering::Less => Value::Boolean(true),
-                            _ => Value::Boolean(false)
-                        },
-                        None => return Err(LoxError::RuntimeError("can't compare values of differing types".to_string(), self.chunk.lines[self.ip - 1]))
-                    });
+                OpCode::Jump => {
+                    let offset = self.read_short();
+                    self.ip += offset as usize;
                 },
-                OP_LESSEREQUAL => {
-                    let (a, b) = self.binary_op()?;
-                    self.stack.push(match a.partial_cmp(&b) {
-                        Some(o) => match o {
-                            cmp::Ordering::Greater => Value::Boolean(false),
-                            _ => Value::Boolean(true)
-                        },
-                        None => return Err(LoxError::RuntimeError("can't compare values of differing types".to_string(), self.chunk.lines[self.ip - 1]))
-                    });
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if self.stack_top == 0 {
+                        return Err(self.error("stack underflow"));
+                    }
+                    if self.stack[self.stack_top - 1].is_falsey() {
+                        self.ip += offset as usize;
+                    }
                 },
-                
-                _ => return Err(LoxError::CompileError(format!("unknown opcode {:04}", instruction).to_string(), self.chunk.lines[self.ip - 1]))
+
+                OpCode::Negate => self.unary_op(|a| -a, "can't negate a non-numeric value")?,
+                OpCode::Add => self.binary_op(|a, b| a + b, "can't add values of differing types")?,
+                OpCode::Subtract => self.binary_op(|a, b| a - b, "can't subtract values of differing types")?,
+                OpCode::Multiply => self.binary_op(|a, b| a * b, "can't multiply values of differing types")?,
+                OpCode::Divide => self.binary_op(|a, b| a / b, "can't divide values of differing types")?,
+                OpCode::Not => self.unary_op(|a| !a, "can't ! a non-boolean value")?,
+                OpCode::ToString => self.unary_op(|a| Ok(to_slot(from_slot(a).stringify())), "")?,
+
+                OpCode::Equal => self.binary_op(|a, b| Ok(to_slot(Value::Boolean(a == b))), "")?,
+                OpCode::NotEqual => self.binary_op(|a, b| Ok(to_slot(Value::Boolean(a != b))), "")?,
+                OpCode::Greater => self.binary_op(
+                    |a, b| a.partial_cmp(&b).map(|o| to_slot(Value::Boolean(o == Ordering::Greater))).ok_or(OpError::TypeMismatch),
+                    "can't compare values of differing types")?,
+                OpCode::GreaterEqual => self.binary_op(
+                    |a, b| a.partial_cmp(&b).map(|o| to_slot(Value::Boolean(o != Ordering::Less))).ok_or(OpError::TypeMismatch),
+                    "can't compare values of differing types")?,
+                OpCode::Lesser => self.binary_op(
+                    |a, b| a.partial_cmp(&b).map(|o| to_slot(Value::Boolean(o == Ordering::Less))).ok_or(OpError::TypeMismatch),
+                    "can't compare values of differing types")?,
+                OpCode::LesserEqual => self.binary_op(
+                    |a, b| a.partial_cmp(&b).map(|o| to_slot(Value::Boolean(o != Ordering::Greater))).ok_or(OpError::TypeMismatch),
+                    "can't compare values of differing types")?,
             }
         }
     }
-}
\ No newline at end of file
+}